@@ -7,6 +7,9 @@ test ${PWD##*/} = newsletter
 # Runs all migrations against a file on the server storing the database. It's
 # important that the migrations are idempotent. It's important that no other
 # process writes into the database while migrations are running.
+#
+# SQLite cannot add a column only if it doesn't exist yet. Such a migration
+# has a line "-- skip if: {query}" and is skipped when the query returns 1.
 
 readonly node="gloss"
 readonly host="doma"
@@ -22,6 +25,15 @@ echo "Syncing migrations dir"
 rsync -av -e "ssh -p ${port}" migrations "${node}@${host}":"${pv_path}"
 
 for m in migrations/*.sql; do
+    skip_if=$(sed -n 's/^-- skip if: //p' "${m}")
+    if [ -n "${skip_if}" ]; then
+        applied=$(ssh -p "${port}" "${node}@${host}" "sqlite3 ${pv_path}/database.db \"${skip_if}\"")
+        if [ "${applied}" = "1" ]; then
+            echo "Skipping ${m}, already applied"
+            continue
+        fi
+    fi
+
     echo "Migrating ${m}"
     ssh -p "${port}" "${node}@${host}" "sqlite3 ${pv_path}/database.db < ${pv_path}/${m}"
 done
@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use shared::{failed_jobs::FailedJobs, rusoto_core::Region, sqs, SqsExt};
+use std::error::Error;
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Deserialize, Debug)]
+struct Conf {
+    /// Path to the sqlite3 file with the `failed_jobs` table.
+    failed_jobs_database_path: String,
+    /// # Default
+    /// The [`Default` trait][default] results in rusoto reading the environment
+    /// variable `AWS_DEFAULT_REGION`.
+    ///
+    /// [default]: https://docs.rs/rusoto_core/0.46.0/rusoto_core/enum.Region.html#default
+    #[serde(default = "Default::default")]
+    region: Region,
+}
+
+/// Prints one failed job per line, oldest first, with tab separated columns
+/// id, created at, receive count, s3 key, queue url, error code and error.
+pub fn list() -> Result<(), Box<dyn Error>> {
+    let conf = envy::from_env::<Conf>()?;
+    let failed_jobs = FailedJobs::open(&conf.failed_jobs_database_path)?;
+
+    for job in failed_jobs.list()? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            job.id,
+            job.created_at,
+            job.receive_count,
+            job.s3_key.as_deref().unwrap_or("-"),
+            job.queue_url,
+            job.error_code.as_deref().unwrap_or("-"),
+            job.error
+        );
+    }
+
+    Ok(())
+}
+
+/// Sends the original message of each job back to the queue it was received
+/// from and removes the job from the table.
+pub async fn requeue(ids: &[&str]) -> Result<(), Box<dyn Error>> {
+    let conf = envy::from_env::<Conf>()?;
+    let failed_jobs = FailedJobs::open(&conf.failed_jobs_database_path)?;
+    let sqs = envy::from_env::<sqs::Conf>()?.client(conf.region);
+
+    for id in ids {
+        let id: i64 = id.parse()?;
+        let job = failed_jobs
+            .get(id)?
+            .ok_or_else(|| format!("There's no failed job with id {}", id))?;
+
+        log::debug!("Requeuing job {:?}", job);
+        sqs.send(job.queue_url.clone(), job.message_body).await?;
+        failed_jobs.delete(id)?;
+
+        println!("Requeued job {} to {}", id, job.queue_url);
+    }
+
+    Ok(())
+}
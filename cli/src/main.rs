@@ -0,0 +1,70 @@
+//! `newsletter-cli` bundles tools which help us operate the pipeline.
+//!
+//! # Failed jobs
+//! Messages which the services gave up on are recorded in the `failed_jobs`
+//! table. We can list them and, once the cause is fixed, send them back to the
+//! queue they came from.
+//!
+//! ```text
+//! newsletter-cli failed-jobs list
+//! newsletter-cli failed-jobs requeue <id> [<id> ...]
+//! ```
+//!
+//! # Run
+//! Runs a newsletter through prtsc, ocr, predictor and sieve without AWS and
+//! prints the offers which we would store. Useful to debug a newsletter which
+//! gave bad results.
+//!
+//! ```text
+//! newsletter-cli run <file.eml|file.html>
+//! ```
+//!
+//! S3 buckets are replaced by directories in the system temp dir. There's no
+//! SQS, each service's handler is called with the output of the previous one.
+//! The OCR is done by Tesseract, which must be installed. We still need the
+//! geckodriver, dealc, voucherc and OpenAI, see the `Conf` in the [`run`]
+//! module for the env vars.
+//!
+//! # Train
+//! Fits the sieve's learned combiner on the offers which were labelled in the
+//! `label` column of the `offers` table and writes the model to given path.
+//! The sieve loads it with `MODEL_PATH`. The database is at `DATABASE_PATH`.
+//!
+//! ```text
+//! newsletter-cli train <model.json>
+//! ```
+
+mod failed_jobs;
+mod run;
+mod train;
+
+use dotenv::dotenv;
+use std::{env, error::Error, process};
+
+const USAGE: &str = "Usage:
+    newsletter-cli failed-jobs list
+    newsletter-cli failed-jobs requeue <id> [<id> ...]
+    newsletter-cli run <file.eml|file.html>
+    newsletter-cli train <model.json>";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["failed-jobs", "list"] => failed_jobs::list(),
+        ["failed-jobs", "requeue", ids @ ..] if !ids.is_empty() => {
+            failed_jobs::requeue(ids).await
+        }
+        ["run", path] => run::run(path).await,
+        ["train", model_path] => train::train(model_path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
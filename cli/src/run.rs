@@ -0,0 +1,210 @@
+use serde::Deserialize;
+use shared::{
+    rusoto_core::RusotoError,
+    rusoto_s3::GetObjectError,
+    s3::{fs::FsS3, NewS3Object, PutConf},
+    S3Ext,
+};
+use std::{env, error::Error, fs, path::Path, process};
+use tokio::sync::Mutex;
+
+const HTML_BUCKET: &str = "html";
+const SCREENSHOT_BUCKET: &str = "screenshots";
+const ANCHOR_BUCKET: &str = "anchors";
+const OCR_BUCKET: &str = "ocr";
+const PREDICTION_BUCKET: &str = "predictions";
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Deserialize, Debug)]
+struct Conf {
+    /// On what address does the proxy to the headless browser sit?
+    gecko_url: String,
+    /// On what URL can we reach dealc to categorize phrases.
+    dealc_url: String,
+    /// On what URL can we reach voucherc to categorize vouchers.
+    voucherc_url: String,
+    /// API key for OpenAI account.
+    openai_key: String,
+    /// Where can we reach OpenAI servers.
+    openai_completion_url: String,
+}
+
+/// Runs the newsletter at given path through prtsc, ocr, predictor and sieve
+/// and prints the offers found in it, one per line with tab separated columns
+/// deal, voucher and link.
+///
+/// The file is either an email in the MIME format if it has the `.eml`
+/// extension, or the html body of the email.
+///
+/// The objects are stored with [`FsS3`], but the services are not chained
+/// through queues. The worker polls until SIGTERM and can't tell that the
+/// pipeline is done, so we call each service's `handle` directly, in the order
+/// the S3 notifications would trigger them. Only a failure is reported
+/// differently, it's returned instead of being retried.
+///
+/// prtsc opens the html in the headless browser by the presigned URL of the
+/// object, which [`FsS3`] gives as a `file://` URL into the temp dir. Therefore
+/// the browser behind `GECKO_URL` must share the filesystem with this process,
+/// i.e. a geckodriver started on the host. The browser in the docker setup
+/// can't read the file.
+pub async fn run(path: &str) -> Result<(), Box<dyn Error>> {
+    let conf = envy::from_env::<Conf>()?;
+    let path = Path::new(path);
+    let key = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format!("Invalid file name {}", path.display()))?
+        .to_string();
+    let html = read_html(path)?;
+
+    let root =
+        env::temp_dir().join(format!("newsletter-cli-{}", process::id()));
+    eprintln!("Objects are stored in {}", root.display());
+    let s3 = || Box::new(FsS3::new(&root));
+
+    s3().put(
+        HTML_BUCKET.to_string(),
+        key.clone(),
+        html.into_bytes(),
+        PutConf::default(),
+    )
+    .await?;
+    let record = |bucket: &str| NewS3Object {
+        region: "local".to_string(),
+        bucket: bucket.to_string(),
+        key: key.clone(),
+    };
+
+    log::info!("Capturing screenshot of {}", key);
+    let browser: Box<dyn prtsc::browser::Headless> =
+        Box::new(prtsc::browser::connect(&conf.gecko_url).await?);
+    let state = prtsc::state::State {
+        conf: prtsc::conf::Conf {
+            gecko_url: conf.gecko_url.clone(),
+            screenshot_bucket_name: SCREENSHOT_BUCKET.to_string(),
+            anchor_bucket_name: ANCHOR_BUCKET.to_string(),
+            max_screenshot_size: usize::MAX,
+            ..Default::default()
+        },
+        browser: Mutex::new(browser),
+        s3: s3(),
+    };
+    prtsc::handle(&state, record(HTML_BUCKET)).await?;
+
+    log::info!("Running OCR on screenshot of {}", key);
+    let state = ocr::state::State {
+        conf: ocr::conf::Conf {
+            ocr_backend: ocr::conf::OcrBackend::Tesseract,
+            ocr_bucket_name: OCR_BUCKET.to_string(),
+            ..Default::default()
+        },
+        vision: Box::new(ocr::tesseract::Tesseract),
+        s3: s3(),
+    };
+    ocr::handle(&state, record(SCREENSHOT_BUCKET)).await?;
+    match s3().get(OCR_BUCKET.to_string(), key.clone()).await {
+        Ok(_) => (),
+        // the OCR stores nothing if there's no text
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => {
+            println!("No text found in the screenshot of {}", key);
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    log::info!("Predicting deals and vouchers in {}", key);
+    let http_client = Box::new(predictor::http_client(&conf.openai_key)?);
+    let predictor_conf = predictor::conf::Conf {
+        dealc_url: conf.dealc_url,
+        voucherc_url: conf.voucherc_url,
+        openai_key: conf.openai_key,
+        openai_completion_url: conf.openai_completion_url,
+        prediction_bucket_name: PREDICTION_BUCKET.to_string(),
+        ..Default::default()
+    };
+    let state = predictor::state::State {
+        http_client,
+        probe_client: predictor::probe_client()?,
+        estimators: predictor::estimate::registry(&predictor_conf)?,
+        conf: predictor_conf,
+        s3: s3(),
+        cache: None,
+    };
+    predictor::handle(&state, record(OCR_BUCKET)).await?;
+
+    log::info!("Selecting offers in {}", key);
+    let state = sieve::state::State {
+        conf: sieve::conf::Conf {
+            anchor_bucket_name: ANCHOR_BUCKET.to_string(),
+            ocr_bucket_name: OCR_BUCKET.to_string(),
+            ..Default::default()
+        },
+        db: sieve::db::open_in_memory()?,
+        s3: s3(),
+        tuning: Default::default(),
+        model: None,
+    };
+    sieve::handle(&state, record(PREDICTION_BUCKET)).await?;
+
+    for offer in sieve::db::offers(&state.db, &key)? {
+        println!(
+            "{}\t{}\t{}",
+            offer.deal,
+            offer.voucher.as_deref().unwrap_or("-"),
+            offer.link.as_deref().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
+
+/// Finds the html part of an email, otherwise the file is expected to be html.
+fn read_html(path: &Path) -> Result<String, Box<dyn Error>> {
+    let content = fs::read(path)?;
+    if path.extension().and_then(|e| e.to_str()) != Some("eml") {
+        return Ok(String::from_utf8(content)?);
+    }
+
+    let mail = mailparse::parse_mail(&content)?;
+    let html = html_part(&mail)
+        .ok_or_else(|| format!("No html part in {}", path.display()))?;
+
+    Ok(html.get_body()?)
+}
+
+fn html_part<'a, 'b>(
+    mail: &'b mailparse::ParsedMail<'a>,
+) -> Option<&'b mailparse::ParsedMail<'a>> {
+    if mail.ctype.mimetype == "text/html" {
+        Some(mail)
+    } else {
+        mail.subparts.iter().find_map(html_part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_html_part_of_email() {
+        let email = b"From: shop@example.com\r\n\
+            Subject: Sale\r\n\
+            Content-Type: multipart/alternative; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            Get 20% off!\r\n\
+            --b\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <p>Get 20% off!</p>\r\n\
+            --b--\r\n";
+
+        let mail = mailparse::parse_mail(email).unwrap();
+        let html = html_part(&mail).unwrap();
+
+        assert_eq!(html.get_body().unwrap().trim(), "<p>Get 20% off!</p>");
+    }
+}
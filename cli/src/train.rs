@@ -0,0 +1,40 @@
+use serde::Deserialize;
+use sieve::combiner::{Kind, Model};
+use sqlite::Connection;
+use std::{error::Error, fs};
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Deserialize, Debug)]
+struct Conf {
+    /// Path to the sqlite3 file with the `offers` table.
+    database_path: String,
+}
+
+/// Fits the sieve's combiner on the labelled offers and writes it as JSON to
+/// given path. Prints how many examples of each kind the model is right about.
+pub fn train(model_path: &str) -> Result<(), Box<dyn Error>> {
+    let conf = envy::from_env::<Conf>()?;
+    let conn = Connection::open(&conf.database_path)?;
+
+    let examples = sieve::db::labelled_examples(&conn)?;
+    let model = Model::train(&examples)?;
+    fs::write(model_path, serde_json::to_string_pretty(&model)?)?;
+
+    for kind in &[Kind::Deal, Kind::Voucher] {
+        let logistic = match kind {
+            Kind::Deal => &model.deal,
+            Kind::Voucher => &model.voucher,
+        };
+        let examples: Vec<_> =
+            examples.iter().filter(|e| e.kind == *kind).collect();
+        let right = examples
+            .iter()
+            .filter(|e| (logistic.predict(&e.features) > 0.5) == e.label)
+            .count();
+        println!("{:?}\t{}/{} right", kind, right, examples.len());
+    }
+
+    println!("Model written to {}", model_path);
+
+    Ok(())
+}
@@ -9,5 +9,5 @@ CREATE TABLE IF NOT EXISTS offers (
     created_at INTEGER(4) NOT NULL DEFAULT (strftime('%s','now'))
 );
 
--- TODO: not idempotent
+-- skip if: SELECT COUNT(*) = 1 FROM pragma_table_info('inbound_emails') WHERE name = 'state';
 ALTER TABLE inbound_emails ADD COLUMN state TEXT DEFAULT 'new';
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS failed_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- the queue from which the message was received, it's requeued there
+    queue_url TEXT NOT NULL,
+    -- NULL if the message couldn't be decoded
+    s3_key TEXT,
+    message_body TEXT NOT NULL,
+    error TEXT NOT NULL,
+    -- how many times was the message received before we gave up on it
+    receive_count INTEGER NOT NULL,
+    -- https://stackoverflow.com/a/26127039/5093093
+    created_at INTEGER(4) NOT NULL DEFAULT (strftime('%s','now'))
+);
@@ -0,0 +1,3 @@
+-- category and code of the error, e.g. 'malformed_input.json'
+-- skip if: SELECT COUNT(*) = 1 FROM pragma_table_info('failed_jobs') WHERE name = 'error_code';
+ALTER TABLE failed_jobs ADD COLUMN error_code TEXT;
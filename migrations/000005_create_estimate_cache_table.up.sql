@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS estimate_cache (
+    -- e.g. 'dealc' or 'open_ai'
+    source TEXT NOT NULL,
+    -- entries of other versions than the one the predictor runs with are
+    -- deleted on its start
+    model_version TEXT NOT NULL,
+    -- sha256 of the text with collapsed whitespace
+    text_hash TEXT NOT NULL,
+    -- json array with an estimate per phrase or word, null if none
+    estimates TEXT NOT NULL,
+    -- https://stackoverflow.com/a/26127039/5093093
+    created_at INTEGER(4) NOT NULL DEFAULT (strftime('%s','now')),
+    PRIMARY KEY (source, model_version, text_hash)
+);
@@ -0,0 +1,3 @@
+-- version of the sieve tuning file the offer was selected with
+-- skip if: SELECT COUNT(*) = 1 FROM pragma_table_info('offers') WHERE name = 'tuning_version';
+ALTER TABLE offers ADD COLUMN tuning_version TEXT;
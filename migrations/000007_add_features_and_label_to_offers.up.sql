@@ -0,0 +1,11 @@
+-- both columns are added in one transaction, so either both exist or neither
+-- skip if: SELECT COUNT(*) = 2 FROM pragma_table_info('offers') WHERE name IN ('features', 'label');
+BEGIN;
+
+-- JSON object with the features the sieve selected the offer by, see
+-- sieve::combiner
+ALTER TABLE offers ADD COLUMN features TEXT;
+-- 1 if a reviewer confirmed the offer, 0 if it's wrong, NULL if not reviewed
+ALTER TABLE offers ADD COLUMN label INTEGER;
+
+COMMIT;
@@ -0,0 +1,63 @@
+-- offers get a stable id and each offer is stored once per newsletter, so that
+-- a redelivered message doesn't duplicate them, see sieve::db::insert
+--
+-- sqlite cannot add a primary key to an existing table, hence the copy. The id
+-- of each offer is its row id, which is the id itself once migrated, so
+-- running this again copies the same offers with the same ids. Duplicates are
+-- merged into the first of them, which takes the most advanced state and the
+-- latest label of the group, so that synced offers aren't synced again and no
+-- review is lost. The transaction makes sure the table isn't lost midway.
+BEGIN;
+
+DROP TABLE IF EXISTS offers_new;
+
+CREATE TABLE offers_new (
+    s3_key VARCHAR (40) NOT NULL,
+    deal TEXT NOT NULL,
+    voucher TEXT,
+    link TEXT,
+    state TEXT NOT NULL DEFAULT 'new',
+    created_at INTEGER(4) NOT NULL DEFAULT (strftime('%s','now')),
+    tuning_version TEXT,
+    features TEXT,
+    label INTEGER,
+    id INTEGER PRIMARY KEY
+);
+
+INSERT INTO offers_new (
+    s3_key, deal, voucher, link, state, created_at, tuning_version, features,
+    label, id
+)
+SELECT o.s3_key, o.deal, o.voucher, o.link,
+    (
+        SELECT d.state FROM offers d
+        WHERE d.s3_key = o.s3_key AND d.deal = o.deal
+            AND IFNULL(d.voucher, '') = IFNULL(o.voucher, '')
+        ORDER BY CASE d.state
+            WHEN 'new' THEN 0 WHEN 'synced' THEN 2 ELSE 1
+        END DESC, d.rowid
+        LIMIT 1
+    ),
+    o.created_at, o.tuning_version, o.features,
+    (
+        SELECT d.label FROM offers d
+        WHERE d.s3_key = o.s3_key AND d.deal = o.deal
+            AND IFNULL(d.voucher, '') = IFNULL(o.voucher, '')
+            AND d.label IS NOT NULL
+        ORDER BY d.rowid DESC
+        LIMIT 1
+    ),
+    o.rowid
+FROM offers o
+WHERE o.rowid IN (
+    SELECT MIN(rowid) FROM offers GROUP BY s3_key, deal, IFNULL(voucher, '')
+);
+
+DROP TABLE offers;
+ALTER TABLE offers_new RENAME TO offers;
+
+-- null vouchers would be distinct from each other in a plain unique index
+CREATE UNIQUE INDEX IF NOT EXISTS offers_s3_key_deal_voucher
+ON offers (s3_key, deal, IFNULL(voucher, ''));
+
+COMMIT;
@@ -1,6 +1,6 @@
 //! Name of each env var is the same as the property but in ALL_CAPS.
 
-use {serde::Deserialize, shared::rusoto_core::Region};
+use {serde::Deserialize, shared::rusoto_core::Region, std::time::Duration};
 
 #[derive(Default, Deserialize, Debug)]
 pub struct Conf {
@@ -15,10 +15,52 @@ pub struct Conf {
     /// [default]: https://docs.rs/rusoto_core/0.46.0/rusoto_core/enum.Region.html#default
     #[serde(default = "Default::default")]
     pub region: Region,
+    /// Which OCR implementation annotates the screenshots.
+    ///
+    /// # Default
+    /// Google Vision
+    #[serde(default)]
+    pub ocr_backend: OcrBackend,
     /// JSON like `{"installed":{"client_id": ... }}`
+    ///
+    /// Only required by the Google Vision backend.
+    #[serde(default)]
     pub gcp_secret: String,
     /// S3 where we store JSON files with OCR information.
     pub ocr_bucket_name: String,
+    /// If enabled, several screenshots are stitched into one image and sent to
+    /// the OCR in one request. See the batching section in the crate docs.
+    #[serde(default)]
+    pub stitch_screenshots: bool,
+    /// If enabled, the OCR is sent the screenshot itself rather than a url to
+    /// download it from. Stitched screenshots are always sent inline.
+    #[serde(default)]
+    pub inline_screenshots: bool,
+    /// For how long is the presigned url of a screenshot valid. The OCR only
+    /// needs it to download the screenshot.
+    ///
+    /// # Default
+    /// 5 minutes
+    pub presigned_url_ttl_secs: Option<u64>,
+}
+
+impl Conf {
+    pub fn presigned_url_ttl(&self) -> Duration {
+        Duration::from_secs(self.presigned_url_ttl_secs.unwrap_or(5 * 60))
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrBackend {
+    Vision,
+    Tesseract,
+}
+
+impl Default for OcrBackend {
+    fn default() -> Self {
+        Self::Vision
+    }
 }
 
 #[cfg(test)]
@@ -39,5 +81,8 @@ mod tests {
         assert_eq!(conf.ocr_bucket_name, "buckettest");
         assert_eq!(conf.gcp_secret, "gcptest");
         assert_eq!(conf.region, Region::EuWest1);
+        assert!(!conf.stitch_screenshots);
+        assert!(!conf.inline_screenshots);
+        assert_eq!(conf.ocr_backend, OcrBackend::Vision);
     }
 }
@@ -1,90 +1,86 @@
-use {
-    shared::rusoto_core::RusotoError,
-    std::{
-        error::Error as StdError,
-        fmt::{self, Debug, Display},
-        io,
-    },
-};
+//! See [`shared::error`] for how errors are categorized. This module converts
+//! errors of the libraries only this service uses.
 
-#[derive(Debug)]
-pub struct Error {
-    message: String,
-    is_recoverable: bool,
-}
+use image::ImageError;
+pub use shared::error::{Category, Error};
 
-impl Error {
-    /// This error gets logged but service continues polling sqs.
-    pub fn new(reason: impl Display) -> Self {
-        Self {
-            message: reason.to_string(),
-            is_recoverable: true,
-        }
-    }
+pub fn vision(e: google_vision1::Error) -> Error {
+    use google_vision1::Error::*;
 
-    /// The service terminates.
-    pub fn fatal(reason: impl Display) -> Self {
-        Self {
-            message: reason.to_string(),
-            is_recoverable: false,
-        }
-    }
+    // the api describes an error in the body, including its status
+    let status = match &e {
+        Failure(res) => Some(res.status().as_u16()),
+        BadRequest(body) => body["error"]["code"].as_u64().map(|s| s as u16),
+        _ => None,
+    };
 
-    pub fn is_recoverable(&self) -> bool {
-        self.is_recoverable
+    match e {
+        UploadSizeLimitExceeded(sent, limit) => Error::new(format!(
+            "Upload size limit of {} reached with attempt of {}",
+            limit, sent
+        ))
+        .with_code("vision_upload_size"),
+        HttpError(_) => Error::transient(e).with_code("vision"),
+        Failure(_) | BadRequest(_) => match status {
+            Some(status) => by_status(status, e),
+            None => Error::fatal(e).with_code("vision"),
+        },
+        MissingAPIKey
+        | MissingToken(_)
+        | Cancelled
+        | FieldClash(_)
+        | JsonDecodeError(_, _)
+        | Io(_) => Error::fatal(e).with_code("vision"),
     }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+/// Rate limits are retried after a while, server errors right away.
+fn by_status(status: u16, e: google_vision1::Error) -> Error {
+    match status {
+        429 => Error::quota(e),
+        500..=599 => Error::transient(e),
+        _ => Error::fatal(e),
     }
+    .with_code("vision")
 }
 
-impl StdError for Error {}
-
-impl From<serde_json::Error> for Error {
-    fn from(e: serde_json::Error) -> Self {
-        Self::new(e)
-    }
+pub fn image(e: ImageError) -> Error {
+    Error::new(&e).with_code("image").with_source(e)
 }
 
-impl<E: Debug> From<RusotoError<E>> for Error {
-    fn from(e: RusotoError<E>) -> Self {
-        Self::fatal(format!("AWS err: {:?}", e))
-    }
-}
-
-impl From<envy::Error> for Error {
-    fn from(e: envy::Error) -> Self {
-        Self::fatal(e)
-    }
-}
-
-impl From<io::Error> for Error {
-    fn from(e: io::Error) -> Self {
-        Self::fatal(e)
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-impl From<google_vision1::Error> for Error {
-    fn from(e: google_vision1::Error) -> Self {
-        use google_vision1::Error::*;
+    #[test]
+    fn it_retries_vision_rate_limits_and_server_errors() {
+        let failure = |status: u16| {
+            google_vision1::Error::Failure(
+                hyper::Response::builder()
+                    .status(status)
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+        };
+        let bad_request = |status: u16| {
+            google_vision1::Error::BadRequest(json!({
+                "error": { "code": status, "status": "RESOURCE_EXHAUSTED" }
+            }))
+        };
 
-        match e {
-            UploadSizeLimitExceeded(sent, limit) => Error::new(format!(
-                "Upload size limit of {} reached with attempt of {}",
-                limit, sent
-            )),
-            HttpError(_)
-            | BadRequest(_)
-            | MissingAPIKey
-            | MissingToken(_)
-            | Cancelled
-            | FieldClash(_)
-            | JsonDecodeError(_, _)
-            | Failure(_)
-            | Io(_) => Error::fatal(e),
-        }
+        let e = vision(failure(429));
+        assert_eq!(e.category(), Category::UpstreamQuota);
+        assert!(e.retry_after().is_some());
+        assert_eq!(
+            vision(bad_request(429)).category(),
+            Category::UpstreamQuota
+        );
+        assert_eq!(vision(failure(503)).category(), Category::TransientNetwork);
+        assert_eq!(vision(failure(403)).category(), Category::FatalInfra);
+        assert_eq!(
+            vision(google_vision1::Error::MissingAPIKey).category(),
+            Category::FatalInfra
+        );
     }
 }
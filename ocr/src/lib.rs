@@ -0,0 +1,263 @@
+//! `ocr` is a microservice which listens to SQS messages created by insertions
+//! into an S3 bucket _IN_. _IN_ persists png screenshot of each newsletter.
+//!
+//! Screenshots are then sent to [Google's Vision API][vision-api] for text
+//! detection, or to a local Tesseract with `OCR_BACKEND=tesseract`. The output
+//! from the text detection is trimmed of unnecessary information and stored as
+//! a JSON file in _OUT_ S3 bucket. The advantage of not storing the parsed OCR
+//! in a database is that S3 allows us to create SQS notifications on
+//! insertion, and therefore follow the same design pattern in many services.
+//! Also running OCR is quite expensive and storage in S3 is cheaper and more
+//! reliable.
+//!
+//! The screenshots aren't public. The OCR downloads each one from a short lived
+//! presigned url, or is sent the screenshot itself with
+//! `INLINE_SCREENSHOTS=true`.
+//!
+//! # Batching
+//! Enabled with `STITCH_SCREENSHOTS=true`, off by default.
+//! [Google Vision APIs][vision-api-pricing] costs $0.0015 per image scanned.
+//!
+//! Because GCP Vision API pricing is per image, we cut costs by stitching as
+//! many screenshots as possible into one image.
+//!
+//! Since the resulting API gives us bounding box information, we can determine
+//! which OCR'd text belongs to which screenshot.
+//!
+//! We keep on receiving messages from the SQS until
+//! a) we have `MAX_BATCH_LEN` of them;
+//! b) the oldest message we keep is reaching the end of its visibility timeout.
+//!
+//! The screenshots are then packed into as few images as the size limit of
+//! Vision API allows. See the [`stitch`] module.
+//!
+//! We can calculate the approximate memory usage by considering that we keep
+//! all the screenshots of a batch in memory, plus one decoded stitched image.
+//!
+//! [vision-api-pricing]: https://cloud.google.com/vision/pricing
+//! [vision-api]: https://cloud.google.com/vision/docs/ocr
+
+pub mod conf;
+pub mod error;
+pub mod prelude;
+pub mod state;
+mod stitch;
+pub mod tesseract;
+pub mod vision;
+
+use async_trait::async_trait;
+use prelude::*;
+use shared::s3::NewS3Object;
+use shared::server::Readiness;
+use shared::vision::Annotation;
+use shared::worker::{BatchHandler, Handler};
+use state::State;
+use vision::{Image, Ocr};
+
+#[async_trait(?Send)]
+impl Handler for State {
+    type Error = Error;
+
+    async fn handle(&self, record: NewS3Object) -> Result<(), Error> {
+        handle(self, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl BatchHandler for State {
+    type Error = Error;
+
+    async fn handle_batch(
+        &self,
+        records: Vec<NewS3Object>,
+    ) -> Vec<Result<(), Error>> {
+        stitch::handle_batch(self, records).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Readiness for State {
+    async fn check(&self) -> Result<(), Error> {
+        self.vision.check().await
+    }
+}
+
+/// 1. Either downloads the screenshot or presigns a short lived url at which
+///    the OCR can download it, see [`Conf::inline_screenshots`].
+///
+/// 2. Runs an OCR job with Vision API and strips unnecessary data from the
+///    response.
+///
+/// 3. Stores the output of the OCR job in a dedicated S3.
+pub async fn handle(state: &State, record: NewS3Object) -> Result<(), Error> {
+    // 1.
+    let image = if state.conf.inline_screenshots {
+        let png = state
+            .s3
+            .get(record.bucket.clone(), record.key.clone())
+            .await?
+            .ok_or_else(|| {
+                Error::new(format!("Screenshot {} not found", record.key))
+                    .with_code("missing_object")
+            })?;
+        Image::Content(png)
+    } else {
+        let url = state
+            .s3
+            .presigned_url(&record, state.conf.presigned_url_ttl())
+            .await?;
+        Image::Uri(url)
+    };
+
+    // 2.
+    let annotation = state.vision.annotate(image).await?;
+
+    // 3.
+    save(state, record.key, annotation).await
+}
+
+/// Stores the annotation as JSON under the same key as the screenshot had.
+async fn save(
+    state: &State,
+    key: String,
+    annotation: Option<Annotation>,
+) -> Result<(), Error> {
+    if let Some(annotation) = annotation {
+        let json = serde_json::to_string(&annotation)?;
+        log::trace!("Saving OCr annotation for {} into s3", key);
+        state
+            .s3
+            .put(
+                state.conf.ocr_bucket_name.clone(),
+                key.clone(),
+                json.into(),
+                shared::s3::PutConf {
+                    content_type: Some("application/json".to_string()),
+                    ..Default::default()
+                }
+                .with_trace_id(&key),
+            )
+            .await?;
+    } else {
+        log::warn!("No text found in image {}", key);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use async_trait::async_trait;
+    use shared::rusoto_core::Region;
+    use shared::s3::memory::MemoryS3;
+    use shared::tests::*;
+    use shared::S3Ext;
+
+    #[tokio::test]
+    async fn it_ocrs_and_uploads_to_s3() {
+        let png_bucket = "png_bucket";
+        let ocr_bucket_name = "ocr_bucket";
+        let object_key = "test_key";
+        let body: Vec<u8> = serde_json::to_string(&Annotation::default())
+            .unwrap()
+            .into();
+        let region = Region::EuWest2;
+
+        let record = NewS3Object {
+            region: region.name().to_string(),
+            bucket: png_bucket.to_string(),
+            key: object_key.to_string(),
+        };
+
+        let s3_stub = S3Stub {
+            bucket: ocr_bucket_name.to_string(),
+            key: object_key.to_string(),
+            body: body.clone(),
+            conf: shared::s3::PutConf {
+                content_type: Some("application/json".to_string()),
+                ..Default::default()
+            }
+            .with_trace_id(object_key),
+            ..Default::default()
+        };
+
+        let vision_stub = VisionStub {
+            annotation: Default::default(),
+            image: Image::Uri(format!(
+                "https://s3-{}.amazonaws.com/{}/{}",
+                region.name(),
+                png_bucket,
+                object_key
+            )),
+        };
+
+        let conf = Conf {
+            ocr_bucket_name: ocr_bucket_name.to_string(),
+            region,
+            ..Default::default()
+        };
+
+        let state = State {
+            conf,
+            s3: Box::new(s3_stub),
+            vision: Box::new(vision_stub),
+        };
+
+        handle(&state, record).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_sends_screenshot_inline() {
+        let record = NewS3Object {
+            region: "eu-west-2".to_string(),
+            bucket: "png_bucket".to_string(),
+            key: "test_key".to_string(),
+        };
+        let s3 = MemoryS3::new();
+        s3.put(
+            record.bucket.clone(),
+            record.key.clone(),
+            vec![1, 2, 3],
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let state = State {
+            conf: Conf {
+                ocr_bucket_name: "ocr_bucket".to_string(),
+                inline_screenshots: true,
+                ..Default::default()
+            },
+            s3: Box::new(s3),
+            vision: Box::new(VisionStub {
+                annotation: Default::default(),
+                image: Image::Content(vec![1, 2, 3]),
+            }),
+        };
+
+        handle(&state, record).await.unwrap();
+    }
+
+    struct VisionStub {
+        image: Image,
+        annotation: Annotation,
+    }
+
+    #[async_trait]
+    impl Ocr for VisionStub {
+        async fn annotate(
+            &self,
+            image: Image,
+        ) -> Result<Option<Annotation>, Error> {
+            assert_eq!(self.image, image);
+            Ok(Some(self.annotation.clone()))
+        }
+
+        async fn check(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,8 @@
 use crate::{prelude::*, vision::Ocr};
-use shared::{S3Ext, SqsExt};
+use shared::S3Ext;
 
 pub struct State {
     pub conf: Conf,
-    pub sqs: Box<dyn SqsExt>,
     pub s3: Box<dyn S3Ext>,
     pub vision: Box<dyn Ocr>,
 }
@@ -0,0 +1,625 @@
+//! Stitches several screenshots vertically into one image, sends it to the OCR
+//! in a single request and splits the annotation back per screenshot by the
+//! coordinates of each word.
+//!
+//! A batch which doesn't fit the Vision API limits is packed into several
+//! stitched images. The stitched image is a jpeg of about the same size as the
+//! screenshots together, and a group whose image is over the limit anyway is
+//! split in halves until each part fits. A screenshot which ends up alone is
+//! annotated the same way as in the non-batched mode.
+//!
+//! The annotation of each screenshot is the same as if it was annotated on its
+//! own, except that the OCR reads the screenshot from the re-encoded stitched
+//! jpeg, so a word on the edge of legibility can be recognized differently.
+
+use crate::{error, prelude::*, state::State, vision::Image};
+use futures::future;
+use image::{
+    imageops, io::Reader, DynamicImage, ImageError, ImageOutputFormat, Rgb,
+    RgbImage,
+};
+use shared::{s3::NewS3Object, vision::Annotation};
+use std::{collections::HashMap, io::Cursor};
+
+/// Vision API rejects requests larger than 10MB. The image content is base64
+/// encoded in the request, which inflates it by a third.
+const MAX_IMAGE_BYTES: usize = 7 * 1024 * 1024;
+/// Larger images are downscaled by Vision API, which would shift the word
+/// coordinates.
+const MAX_IMAGE_PIXELS: u64 = 75_000_000;
+/// The same quality prtsc encodes the screenshots with.
+const JPEG_QUALITY: u8 = 90;
+/// White space between two screenshots so that OCR doesn't merge text from
+/// both into one block.
+const GAP: u32 = 50;
+
+struct Screenshot {
+    /// Position of the record in the batch.
+    index: usize,
+    record: NewS3Object,
+    jpeg: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// Screenshots which are annotated with one request.
+struct Part<'a> {
+    screenshots: Vec<&'a Screenshot>,
+    /// The stitched jpeg and the y coordinate at which each screenshot starts
+    /// in it. None if the part is a single screenshot.
+    stitched: Option<(Vec<u8>, Vec<u32>)>,
+}
+
+/// 1. Downloads all screenshots.
+///
+/// 2. Packs them into groups which fit the Vision API limits.
+///
+/// 3. Stitches each group, splitting it if the image is over the limit.
+///
+/// 4. Annotates each part with one request and stores the output per
+///    screenshot.
+pub async fn handle_batch(
+    state: &State,
+    records: Vec<NewS3Object>,
+) -> Vec<Result<(), Error>> {
+    let mut results: Vec<Result<(), Error>> =
+        records.iter().map(|_| Ok(())).collect();
+
+    // 1.
+    let downloads = records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| download(state, index, record));
+    let mut screenshots = vec![];
+    for (index, screenshot) in
+        future::join_all(downloads).await.into_iter().enumerate()
+    {
+        match screenshot {
+            Ok(screenshot) => screenshots.push(screenshot),
+            Err(e) => results[index] = Err(e),
+        }
+    }
+
+    // 2.
+    let sizes: Vec<_> = screenshots
+        .iter()
+        .map(|s| (s.jpeg.len(), s.width, s.height))
+        .collect();
+    for group in pack(&sizes) {
+        let group: Vec<_> =
+            group.into_iter().map(|i| &screenshots[i]).collect();
+
+        // 3.
+        let parts = match stitch_within(&group, MAX_IMAGE_BYTES) {
+            Ok(parts) => parts,
+            Err(e) => {
+                log::error!("Cannot stitch {} screenshots: {}", group.len(), e);
+                for screenshot in group {
+                    results[screenshot.index] = Err(e.clone());
+                }
+                continue;
+            }
+        };
+
+        // 4.
+        for part in parts {
+            let outcomes = annotate_part(state, &part).await;
+            for (screenshot, outcome) in part.screenshots.iter().zip(outcomes) {
+                if let Err(e) = &outcome {
+                    log::error!(
+                        "Cannot annotate screenshot {}: {}",
+                        screenshot.record.key,
+                        e
+                    );
+                }
+                results[screenshot.index] = outcome;
+            }
+        }
+    }
+
+    results
+}
+
+async fn download(
+    state: &State,
+    index: usize,
+    record: NewS3Object,
+) -> Result<Screenshot, Error> {
+    let jpeg = state
+        .s3
+        .get(record.bucket.clone(), record.key.clone())
+        .await?
+        .ok_or_else(|| {
+            Error::new(format!("Screenshot {} not found", record.key))
+                .with_code("missing_object")
+        })?;
+    // reads only the header, the image is decoded when stitched
+    let (width, height) = Reader::new(Cursor::new(&jpeg))
+        .with_guessed_format()
+        .map_err(|e| error::image(ImageError::IoError(e)))?
+        .into_dimensions()
+        .map_err(error::image)?;
+
+    Ok(Screenshot {
+        index,
+        record,
+        jpeg,
+        width,
+        height,
+    })
+}
+
+/// Returns the outcome for each screenshot of the part, in its order. Once the
+/// part is annotated, the output of each screenshot is stored on its own, so
+/// that only the screenshots whose output wasn't stored are retried.
+async fn annotate_part(
+    state: &State,
+    part: &Part<'_>,
+) -> Vec<Result<(), Error>> {
+    let (jpeg, offsets) = match (&part.stitched, part.screenshots.as_slice()) {
+        (Some(stitched), _) => stitched,
+        (None, [screenshot]) => {
+            return vec![crate::handle(state, screenshot.record.clone()).await];
+        }
+        (None, _) => unreachable!("Only single screenshots aren't stitched"),
+    };
+
+    let annotations =
+        match state.vision.annotate(Image::Content(jpeg.clone())).await {
+            Ok(Some(annotation)) => split(annotation, offsets),
+            Ok(None) => vec![None; part.screenshots.len()],
+            Err(e) => return vec![Err(e); part.screenshots.len()],
+        };
+
+    let saves = part.screenshots.iter().zip(annotations).map(
+        |(screenshot, annotation)| {
+            crate::save(state, screenshot.record.key.clone(), annotation)
+        },
+    );
+    future::join_all(saves).await
+}
+
+/// The screenshots' sizes which [`pack`] budgets with are only an estimate of
+/// the stitched image's size. Stitches the group and splits it in halves
+/// until each stitched image is within given number of bytes, or is a single
+/// screenshot. The parts are in the order of the group.
+fn stitch_within<'a>(
+    group: &[&'a Screenshot],
+    max_bytes: usize,
+) -> Result<Vec<Part<'a>>, Error> {
+    let mut parts = vec![];
+    let mut pending = vec![group.to_vec()];
+    while let Some(mut screenshots) = pending.pop() {
+        if screenshots.len() == 1 {
+            parts.push(Part {
+                screenshots,
+                stitched: None,
+            });
+            continue;
+        }
+
+        let (jpeg, offsets) = stitch(&screenshots)?;
+        if jpeg.len() <= max_bytes {
+            log::debug!(
+                "Stitched {} screenshots into an image of {} bytes",
+                screenshots.len(),
+                jpeg.len()
+            );
+            parts.push(Part {
+                screenshots,
+                stitched: Some((jpeg, offsets)),
+            });
+        } else {
+            log::debug!(
+                "Stitched image of {} bytes is over the limit, splitting {} \
+                screenshots",
+                jpeg.len(),
+                screenshots.len()
+            );
+            let second_half = screenshots.split_off(screenshots.len() / 2);
+            // the first half is popped first
+            pending.push(second_half);
+            pending.push(screenshots);
+        }
+    }
+
+    Ok(parts)
+}
+
+/// Greedily groups consecutive images of given `(bytes, width, height)` so that
+/// each group is within the Vision API limits once stitched, assuming the
+/// stitched jpeg is about as large as the images together. An image which
+/// alone exceeds the limits is in a group of its own. Returns indexes into
+/// the input.
+fn pack(sizes: &[(usize, u32, u32)]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut group = vec![];
+    let (mut bytes, mut width, mut height) = (0, 0, 0);
+
+    for (index, (b, w, h)) in sizes.iter().copied().enumerate() {
+        let new_width = width.max(w) as u64;
+        let new_height = if group.is_empty() {
+            h as u64
+        } else {
+            height + (GAP + h) as u64
+        };
+        let fits = bytes + b <= MAX_IMAGE_BYTES
+            && new_width * new_height <= MAX_IMAGE_PIXELS;
+
+        if !fits && !group.is_empty() {
+            groups.push(std::mem::take(&mut group));
+            bytes = b;
+            width = w;
+            height = h as u64;
+        } else {
+            bytes += b;
+            width = new_width as u32;
+            height = new_height;
+        }
+        group.push(index);
+    }
+
+    if !group.is_empty() {
+        groups.push(group);
+    }
+
+    groups
+}
+
+/// Places the screenshots one under another on a white canvas and encodes it
+/// as jpeg. Returns the jpeg and the y coordinate at which each screenshot
+/// starts.
+fn stitch(group: &[&Screenshot]) -> Result<(Vec<u8>, Vec<u32>), Error> {
+    let width = group.iter().map(|s| s.width).max().unwrap_or_default();
+    let height = group.iter().map(|s| s.height).sum::<u32>()
+        + GAP * (group.len().saturating_sub(1) as u32);
+
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    let mut offsets = Vec::with_capacity(group.len());
+    let mut offset = 0;
+    for screenshot in group {
+        let image = image::load_from_memory(&screenshot.jpeg)
+            .map_err(error::image)?
+            .to_rgb8();
+        imageops::replace(&mut canvas, &image, 0, offset);
+        offsets.push(offset);
+        offset += image.height() + GAP;
+    }
+
+    let mut jpeg = vec![];
+    DynamicImage::ImageRgb8(canvas)
+        .write_to(&mut jpeg, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(error::image)?;
+
+    Ok((jpeg, offsets))
+}
+
+/// Assigns each word to the screenshot in which its vertical centre lies and
+/// moves its coordinates so that they're relative to the screenshot.
+///
+/// The text of each screenshot is rebuilt from its words, each followed by the
+/// whitespace which followed it in the text of the stitched image. Text before
+/// the first word goes to the screenshot of the first word.
+///
+/// Block and paragraph indexes are unique in the stitched annotation, so they
+/// are renumbered from 0 in each screenshot in the order they first appear.
+fn split(annotation: Annotation, offsets: &[u32]) -> Vec<Option<Annotation>> {
+    let Annotation { text, words } = annotation;
+
+    // finds where in the text each word is, in order
+    let mut spans = Vec::with_capacity(words.len());
+    let mut cursor = 0;
+    for word in &words {
+        let start = text[cursor..].find(&word.word).map(|i| cursor + i);
+        if let Some(start) = start {
+            cursor = start + word.word.len();
+        }
+        spans.push(start);
+    }
+
+    // each word with the whitespace which follows it up until the next word
+    let mut pieces = vec![String::new(); words.len()];
+    let mut next_start = text.len();
+    for (index, start) in spans.into_iter().enumerate().rev() {
+        pieces[index] = match start {
+            Some(start) => {
+                let piece = text[start..next_start].to_string();
+                next_start = start;
+                piece
+            }
+            None => format!("{} ", words[index].word),
+        };
+    }
+    if let Some(first) = pieces.first_mut() {
+        first.insert_str(0, &text[..next_start]);
+    }
+
+    let mut annotations = vec![Annotation::default(); offsets.len()];
+    let mut blocks = vec![HashMap::new(); offsets.len()];
+    let mut paragraphs = vec![HashMap::new(); offsets.len()];
+    for (mut word, piece) in words.into_iter().zip(pieces) {
+        let centre = (word.top_left.y + word.bottom_right.y) / 2;
+        // a word in the gap belongs to the screenshot above it
+        let part = offsets
+            .iter()
+            .rposition(|offset| centre >= *offset as i32)
+            .unwrap_or(0);
+        let offset = offsets[part] as i32;
+
+        word.top_left.y -= offset;
+        word.bottom_right.y -= offset;
+        word.block = word.block.map(|b| renumber(&mut blocks[part], b));
+        word.paragraph =
+            word.paragraph.map(|p| renumber(&mut paragraphs[part], p));
+        annotations[part].text.push_str(&piece);
+        annotations[part].words.push(word);
+    }
+
+    annotations
+        .into_iter()
+        .map(|a| (!a.words.is_empty()).then(|| a))
+        .collect()
+}
+
+/// Gives each index the next free number the first time it's seen.
+fn renumber(seen: &mut HashMap<u32, u32>, index: u32) -> u32 {
+    let next = seen.len() as u32;
+    *seen.entry(index).or_insert(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::vision::Word;
+
+    const MB: usize = 1024 * 1024;
+
+    #[test]
+    fn it_packs_images_within_limits() {
+        assert_eq!(pack(&[]), Vec::<Vec<usize>>::new());
+
+        assert_eq!(
+            pack(&[(MB, 1000, 5000), (MB, 1000, 5000), (MB, 1200, 5000)]),
+            vec![vec![0, 1, 2]]
+        );
+
+        // by bytes
+        assert_eq!(
+            pack(&[(4 * MB, 100, 100), (4 * MB, 100, 100), (MB, 100, 100)]),
+            vec![vec![0], vec![1, 2]]
+        );
+
+        // by pixels
+        assert_eq!(
+            pack(&[(MB, 1000, 40_000), (MB, 1000, 40_000)]),
+            vec![vec![0], vec![1]]
+        );
+
+        // too large on its own
+        assert_eq!(
+            pack(&[(MB, 100, 100), (8 * MB, 100, 100), (MB, 100, 100)]),
+            vec![vec![0], vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn it_stitches_screenshots() {
+        let black = |width, height| {
+            screenshot(RgbImage::from_pixel(width, height, Rgb([0, 0, 0])))
+        };
+        let (a, b) = (black(10, 20), black(15, 30));
+
+        let (jpeg, offsets) = stitch(&[&a, &b]).unwrap();
+
+        assert_eq!(offsets, vec![0, 20 + GAP]);
+        let image = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (15, 20 + GAP + 30));
+        // jpeg is lossy
+        let is = |x, y, value: i32| {
+            let pixel = image.get_pixel(x, y);
+            pixel.0.iter().all(|c| (*c as i32 - value).abs() < 10)
+        };
+        assert!(is(5, 10, 0));
+        assert!(is(12, 10, 255));
+        assert!(is(5, 20 + GAP / 2, 255));
+        assert!(is(12, 20 + GAP + 5, 0));
+    }
+
+    #[test]
+    fn it_keeps_stitched_images_within_limit() {
+        let screenshots: Vec<_> = (0..4)
+            .map(|seed| Screenshot {
+                index: seed as usize,
+                ..screenshot(newsletter(seed))
+            })
+            .collect();
+        let group: Vec<_> = screenshots.iter().collect();
+        let bytes: usize = screenshots.iter().map(|s| s.jpeg.len()).sum();
+
+        // the estimate pack budgets with holds
+        let (jpeg, _) = stitch(&group).unwrap();
+        assert!(
+            jpeg.len() < bytes * 3 / 2,
+            "stitched {} bytes, screenshots {} bytes",
+            jpeg.len(),
+            bytes
+        );
+
+        let parts = stitch_within(&group, jpeg.len()).unwrap();
+        assert_eq!(parts.len(), 1);
+
+        let limit = jpeg.len() - 1;
+        let parts = stitch_within(&group, limit).unwrap();
+        assert_eq!(parts.len(), 2);
+        for (part, indexes) in parts.iter().zip(&[[0, 1], [2, 3]]) {
+            let (jpeg, _) = part.stitched.as_ref().unwrap();
+            assert!(jpeg.len() <= limit);
+            assert_eq!(
+                part.screenshots.iter().map(|s| s.index).collect::<Vec<_>>(),
+                indexes
+            );
+        }
+
+        let parts = stitch_within(&group, 0).unwrap();
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|p| p.stitched.is_none()));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| p.screenshots[0].index)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn it_splits_annotation_as_if_unstitched() {
+        let word = |w: &str, y, block, paragraph| Word {
+            word: w.to_string(),
+            top_left: (3, y).into(),
+            bottom_right: (30, y + 12).into(),
+            block: Some(block),
+            paragraph: Some(paragraph),
+            ..Default::default()
+        };
+        let first = Annotation {
+            // the bullet is in the text but not among the words
+            text: "• Summer sale
+20% off
+"
+            .to_string(),
+            words: vec![
+                word("Summer", 5, 0, 0),
+                word("sale", 5, 0, 0),
+                word("20%", 40, 1, 1),
+            ],
+        };
+        let second = Annotation {
+            text: "Use code SUMMER
+Ends Sunday
+"
+            .to_string(),
+            words: vec![
+                word("Use", 0, 0, 0),
+                word("code", 0, 0, 0),
+                word("SUMMER", 0, 0, 0),
+                word("Ends", 30, 0, 1),
+                word("Sunday", 30, 0, 1),
+            ],
+        };
+        let offsets = vec![0, 100 + GAP];
+
+        // what the OCR returns for the stitched image, the indexes continue
+        // after those of the first screenshot
+        let mut stitched = first.clone();
+        stitched.text.push_str(&second.text);
+        stitched
+            .words
+            .extend(second.words.iter().cloned().map(|mut w| {
+                w.top_left.y += offsets[1] as i32;
+                w.bottom_right.y += offsets[1] as i32;
+                w.block = w.block.map(|b| b + 2);
+                w.paragraph = w.paragraph.map(|p| p + 2);
+                w
+            }));
+
+        assert_eq!(split(stitched, &offsets), vec![Some(first), Some(second)]);
+    }
+
+    fn screenshot(image: RgbImage) -> Screenshot {
+        let (width, height) = image.dimensions();
+        let mut jpeg = vec![];
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .unwrap();
+
+        Screenshot {
+            index: 0,
+            record: NewS3Object {
+                region: "eu-west-1".to_string(),
+                bucket: "bucket".to_string(),
+                key: "key".to_string(),
+            },
+            jpeg,
+            width,
+            height,
+        }
+    }
+
+    /// Resembles a newsletter: a noisy banner on top and lines of text below.
+    fn newsletter(seed: u32) -> RgbImage {
+        let mut state = seed.wrapping_mul(2_654_435_761).max(1);
+        let mut random = move || {
+            // xorshift
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        let (width, height) = (600, 1200);
+        let mut image = RgbImage::from_pixel(width, height, Rgb([255; 3]));
+        for y in 0..200 {
+            for x in 0..width {
+                let r = random();
+                image.put_pixel(x, y, Rgb([r as u8, (r >> 8) as u8, 128]));
+            }
+        }
+        for line in (220..height - 20).step_by(30) {
+            for x in (20..width - 20).step_by(6) {
+                if random() % 5 == 0 {
+                    continue; // space between words
+                }
+                for y in line..line + 12 {
+                    for dx in 0..4 {
+                        image.put_pixel(x + dx, y, Rgb([20; 3]));
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    #[test]
+    fn it_splits_annotation() {
+        let word = |w: &str, y| Word {
+            word: w.to_string(),
+            top_left: (0, y).into(),
+            bottom_right: (10, y + 10).into(),
+            ..Default::default()
+        };
+        let offsets = vec![0, 150, 300];
+        let annotation = Annotation {
+            text: "Get 20%\noff!\nCode SUMMER\n".to_string(),
+            words: vec![
+                word("Get", 10),
+                word("20%", 10),
+                word("off!", 40),
+                word("Code", 160),
+                word("SUMMER", 160),
+            ],
+        };
+
+        let annotations = split(annotation, &offsets);
+
+        assert_eq!(
+            annotations,
+            vec![
+                Some(Annotation {
+                    text: "Get 20%\noff!\n".to_string(),
+                    words: vec![
+                        word("Get", 10),
+                        word("20%", 10),
+                        word("off!", 40)
+                    ],
+                }),
+                Some(Annotation {
+                    text: "Code SUMMER\n".to_string(),
+                    words: vec![word("Code", 10), word("SUMMER", 10)],
+                }),
+                None,
+            ]
+        );
+    }
+}
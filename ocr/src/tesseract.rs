@@ -0,0 +1,259 @@
+//! Local OCR with [Tesseract][tesseract]. The `tesseract` binary must be in
+//! `PATH`. We pipe the image into it and parse its TSV output, which has a row
+//! with a bounding box per recognized word.
+//!
+//! [tesseract]: https://github.com/tesseract-ocr/tesseract
+
+use crate::{
+    prelude::*,
+    vision::{Image, Ocr},
+};
+use async_trait::async_trait;
+use shared::{
+    metrics, reqwest,
+    vision::{Annotation, Word},
+};
+use std::process::Stdio;
+use tokio::{fs, io::AsyncWriteExt, process::Command};
+
+/// TSV rows on this level are words. Other levels are pages, blocks,
+/// paragraphs and lines.
+const WORD_LEVEL: &str = "5";
+
+pub struct Tesseract;
+
+#[async_trait]
+impl Ocr for Tesseract {
+    async fn annotate(
+        &self,
+        image: Image,
+    ) -> Result<Option<Annotation>, Error> {
+        let content = match image {
+            Image::Content(content) => content,
+            Image::Uri(uri) => fetch(&uri).await?,
+        };
+
+        let tsv = metrics::time("tesseract", run(content)).await?;
+        Ok(from_tsv(&tsv))
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let status = Command::new("tesseract")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::fatal(format!("Tesseract exited with {}", status))
+                .with_code("tesseract"))
+        }
+    }
+}
+
+/// Reads local files directly, otherwise downloads the image.
+async fn fetch(uri: &str) -> Result<Vec<u8>, Error> {
+    log::trace!("Fetching image at {}", uri);
+    if let Some(path) = uri.strip_prefix("file://") {
+        return Ok(fs::read(path).await?);
+    }
+
+    let res = reqwest::get(uri).await?.error_for_status()?;
+    Ok(res.bytes().await?.to_vec())
+}
+
+async fn run(content: Vec<u8>) -> Result<String, Error> {
+    let mut child = Command::new("tesseract")
+        .args(&["stdin", "stdout", "tsv"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // we must read the output while writing the input, otherwise both
+    // processes can end up waiting for each other on full pipes
+    let mut stdin = child.stdin.take().expect("Stdin is piped");
+    let write = async move {
+        stdin.write_all(&content).await?;
+        // closes the pipe so that tesseract knows the image is complete
+        drop(stdin);
+        Ok::<_, std::io::Error>(())
+    };
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    let output = output?;
+
+    if !output.status.success() {
+        return Err(Error::new(format!(
+            "Tesseract exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+        .with_code("tesseract"));
+    }
+    written?;
+
+    String::from_utf8(output.stdout)
+        .map_err(|e| Error::new(e).with_code("tesseract"))
+}
+
+/// Collects the word rows. Words on the same line are separated by a space in
+/// the text, each line ends with a newline.
+fn from_tsv(tsv: &str) -> Option<Annotation> {
+    let mut text = String::new();
+    let mut words = vec![];
+    let mut last_line: Option<[i32; 4]> = None;
+    // tesseract numbers blocks per page and paragraphs per block, we want them
+    // unique in the annotation
+    let mut block_index = 0;
+    let mut paragraph_index = 0;
+
+    // the first row is a header
+    for row in tsv.lines().skip(1) {
+        // level, page, block, paragraph, line, word, left, top, width,
+        // height, confidence, text
+        let columns: Vec<_> = row.split('\t').collect();
+        if columns.len() != 12 || columns[0] != WORD_LEVEL {
+            continue;
+        }
+
+        let word = columns[11].trim();
+        let numbers: Option<Vec<i32>> =
+            columns[1..10].iter().map(|c| c.parse().ok()).collect();
+        let numbers = match numbers {
+            Some(numbers) if !word.is_empty() => numbers,
+            _ => continue,
+        };
+        let (left, top, width, height) =
+            (numbers[5], numbers[6], numbers[7], numbers[8]);
+
+        let line = [numbers[0], numbers[1], numbers[2], numbers[3]];
+        match last_line {
+            Some(last_line) if last_line == line => text.push(' '),
+            Some(last_line) => {
+                text.push('\n');
+                if last_line[..2] != line[..2] {
+                    block_index += 1;
+                }
+                if last_line[..3] != line[..3] {
+                    paragraph_index += 1;
+                }
+            }
+            None => (),
+        }
+        last_line = Some(line);
+
+        // -1 for rows which aren't words, otherwise between 0 and 100
+        let confidence = columns[10]
+            .parse::<f32>()
+            .ok()
+            .filter(|c| *c >= 0.0)
+            .map(|c| c / 100.0);
+
+        text.push_str(word);
+        words.push(Word {
+            word: word.to_string(),
+            top_left: (left, top).into(),
+            bottom_right: (left + width, top + height).into(),
+            block: Some(block_index),
+            paragraph: Some(paragraph_index),
+            confidence,
+        });
+    }
+
+    if words.is_empty() {
+        None
+    } else {
+        text.push('\n');
+        Some(Annotation { text, words })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_tsv() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\t\
+            left\ttop\twidth\theight\tconf\ttext\n\
+            1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t\n\
+            2\t1\t1\t0\t0\t0\t10\t10\t200\t50\t-1\t\n\
+            5\t1\t1\t1\t1\t1\t10\t10\t50\t20\t96.5\tGet\n\
+            5\t1\t1\t1\t1\t2\t70\t10\t60\t20\t95.1\t20%\n\
+            5\t1\t1\t1\t2\t1\t10\t40\t40\t20\t91.0\toff!\n\
+            5\t1\t1\t1\t2\t2\t60\t40\t40\t20\t-1\t \n\
+            5\t1\t2\t1\t1\t1\t10\t100\t80\t20\t89.9\tSUMMER20\n";
+
+        let annotation = from_tsv(tsv).unwrap();
+
+        assert_eq!(annotation.text, "Get 20%\noff!\nSUMMER20\n");
+        assert_eq!(
+            annotation.words,
+            vec![
+                Word {
+                    word: "Get".to_string(),
+                    top_left: (10, 10).into(),
+                    bottom_right: (60, 30).into(),
+                    block: Some(0),
+                    paragraph: Some(0),
+                    confidence: Some(96.5 / 100.0),
+                },
+                Word {
+                    word: "20%".to_string(),
+                    top_left: (70, 10).into(),
+                    bottom_right: (130, 30).into(),
+                    block: Some(0),
+                    paragraph: Some(0),
+                    confidence: Some(95.1 / 100.0),
+                },
+                Word {
+                    word: "off!".to_string(),
+                    top_left: (10, 40).into(),
+                    bottom_right: (50, 60).into(),
+                    block: Some(0),
+                    paragraph: Some(0),
+                    confidence: Some(91.0 / 100.0),
+                },
+                Word {
+                    word: "SUMMER20".to_string(),
+                    top_left: (10, 100).into(),
+                    bottom_right: (90, 120).into(),
+                    block: Some(1),
+                    paragraph: Some(1),
+                    confidence: Some(89.9 / 100.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_returns_none_if_no_words() {
+        assert_eq!(from_tsv(""), None);
+        assert_eq!(
+            from_tsv(
+                "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\t\
+                left\ttop\twidth\theight\tconf\ttext\n\
+                1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t\n"
+            ),
+            None
+        );
+    }
+
+    /// Requires tesseract to be installed.
+    #[ignore]
+    #[tokio::test]
+    async fn it_uses_tesseract() {
+        let image_url =
+        "https://upload.wikimedia.org/wikipedia/commons/d/d9/Plain_text.png";
+
+        let annotation = Tesseract
+            .annotate(Image::Uri(image_url.to_string()))
+            .await
+            .unwrap();
+        assert!(annotation.is_some());
+    }
+}
@@ -1,26 +1,35 @@
 //! https://cloud.google.com/vision/quotas
 //! https://cloud.google.com/vision/docs/supported-files
 
-use crate::prelude::*;
+use crate::{error, prelude::*};
 use async_trait::async_trait;
 use google_vision1::api::{
-    AnnotateImageRequest, BatchAnnotateImagesRequest, Feature, Image,
-    ImageSource,
+    AnnotateImageRequest, BatchAnnotateImagesRequest, Feature, Image as GImage,
+    ImageSource, Scope,
 };
 use hyper_rustls::HttpsConnector;
 use oauth2::{ServiceAccountAuthenticator, ServiceAccountKey};
-use shared::vision::*;
+use shared::{metrics, vision::*};
 
 pub use google_vision1::Vision;
 
 #[async_trait]
 pub trait Ocr {
-    /// Given a URL for an image, it sends it to GCP Vision APIs and performs
-    /// OCR annotation.
-    async fn annotate(
-        &self,
-        image_url: String,
-    ) -> Result<Option<Annotation>, Error>;
+    /// Given an image, it sends it to GCP Vision APIs and performs OCR
+    /// annotation.
+    async fn annotate(&self, image: Image)
+        -> Result<Option<Annotation>, Error>;
+
+    /// Errors if the OCR provider cannot be used.
+    async fn check(&self) -> Result<(), Error>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Image {
+    /// The OCR provider downloads the image itself.
+    Uri(String),
+    /// Encoded image, such as a png file, sent along with the request.
+    Content(Vec<u8>),
 }
 
 /// Creates a new Google client, ready to be used for querying the Vision APIs.
@@ -40,28 +49,49 @@ pub async fn new(gcp_secret: &str) -> Result<Vision, Error> {
 impl Ocr for Vision {
     async fn annotate(
         &self,
-        image_url: String,
+        image: Image,
     ) -> Result<Option<Annotation>, Error> {
-        log::trace!("Getting annotation for image at {}", image_url);
+        let image = match image {
+            Image::Uri(image_url) => {
+                log::trace!("Getting annotation for image at {}", image_url);
+                GImage {
+                    source: Some(ImageSource {
+                        image_uri: Some(image_url),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            }
+            Image::Content(content) => {
+                log::trace!(
+                    "Getting annotation for image of {} bytes",
+                    content.len()
+                );
+                GImage {
+                    content: Some(base64::encode(content)),
+                    ..Default::default()
+                }
+            }
+        };
+
         let annotate_req = BatchAnnotateImagesRequest {
             requests: Some(vec![AnnotateImageRequest {
                 features: Some(vec![Feature {
                     type_: Some("TEXT_DETECTION".to_string()),
                     ..Default::default()
                 }]),
-                image: Some(Image {
-                    source: Some(ImageSource {
-                        image_uri: Some(image_url),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                }),
+                image: Some(image),
                 ..Default::default()
             }]),
             ..Default::default()
         };
 
-        let (_, data) = self.images().annotate(annotate_req).doit().await?;
+        let (_, data) = metrics::time(
+            "vision",
+            self.images().annotate(annotate_req).doit(),
+        )
+        .await
+        .map_err(error::vision)?;
         let annotation = data
             .responses
             .and_then(|mut r| r.pop()) // we only request one image
@@ -73,10 +103,22 @@ impl Ocr for Vision {
             })
             .ok_or_else(|| {
                 Error::new("Empty response was returned from OCR")
+                    .with_code("empty_response")
             })?;
 
         Ok(Annotation::from(annotation))
     }
+
+    /// The token is cached, so this only calls GCP when the token expires.
+    async fn check(&self) -> Result<(), Error> {
+        self.auth
+            .token(&[Scope::CloudPlatform])
+            .await
+            .map_err(|e| {
+                Error::transient(&e).with_code("vision_auth").with_source(e)
+            })?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -97,7 +139,10 @@ mod tests {
         let image_url =
         "https://upload.wikimedia.org/wikipedia/commons/d/d9/Plain_text.png";
 
-        let annotation = vision.annotate(image_url.to_string()).await.unwrap();
+        let annotation = vision
+            .annotate(Image::Uri(image_url.to_string()))
+            .await
+            .unwrap();
         let _json = serde_json::to_string(&annotation).unwrap();
     }
 }
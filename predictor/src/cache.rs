@@ -0,0 +1,206 @@
+//! Retailers send near identical footers and banners every day. We cache the
+//! estimates of each source by the text they were given, so that we don't ask
+//! the classifiers and OpenAI about the same text over and over.
+//!
+//! Entries are keyed by the source, its model version and the hash of the text
+//! with collapsed whitespace. Case is kept, it matters to vouchers. When the
+//! predictor starts, it deletes entries of other model versions and entries
+//! older than the TTL. Expired entries are also ignored on lookup.
+//!
+//! The cache is an optimization, therefore it logs its errors instead of
+//! failing the message.
+
+use crate::prelude::*;
+use sha2::{Digest, Sha256};
+use shared::{document::Source, metrics};
+use sqlite::{Connection, State};
+
+/// A week.
+const DEFAULT_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+const MIGRATION_05: &str =
+    include_str!("../../migrations/000005_create_estimate_cache_table.up.sql");
+
+pub struct Cache {
+    conn: Connection,
+    ttl_secs: i64,
+    dealc_model_version: String,
+    voucherc_model_version: String,
+    openai_model_version: String,
+}
+
+impl Cache {
+    pub fn open(database_path: &str, conf: &Conf) -> Result<Self, Error> {
+        let conn = Connection::open(database_path)?;
+        conn.execute(MIGRATION_05)?;
+
+        let cache = Self {
+            conn,
+            ttl_secs: conf.estimate_cache_ttl_secs.unwrap_or(DEFAULT_TTL_SECS),
+            dealc_model_version: conf.dealc_model_version.clone(),
+            voucherc_model_version: conf.voucherc_model_version.clone(),
+            openai_model_version: conf.openai_model_version.clone(),
+        };
+        cache.prune()?;
+
+        Ok(cache)
+    }
+
+    /// Estimates of the text, if the source has seen it before.
+    pub fn get(&self, source: &Source, text: &str) -> Option<Vec<Option<f64>>> {
+        let estimates = self.select(source, text).unwrap_or_else(|e| {
+            log::warn!("Cannot read estimate cache: {}", e);
+            None
+        });
+
+        let result = if estimates.is_some() { "hit" } else { "miss" };
+        metrics::ESTIMATE_CACHE_LOOKUPS
+            .with_label_values(&[source.as_str(), result])
+            .inc();
+
+        estimates
+    }
+
+    pub fn insert(
+        &self,
+        source: &Source,
+        text: &str,
+        estimates: &[Option<f64>],
+    ) {
+        if let Err(e) = self.upsert(source, text, estimates) {
+            log::warn!("Cannot write estimate cache: {}", e);
+        }
+    }
+
+    fn select(
+        &self,
+        source: &Source,
+        text: &str,
+    ) -> Result<Option<Vec<Option<f64>>>, Error> {
+        let mut statement = self.conn.prepare(
+            "SELECT estimates FROM estimate_cache \
+            WHERE source = ? AND model_version = ? AND text_hash = ? \
+            AND created_at >= strftime('%s','now') - ?",
+        )?;
+        statement.bind(1, source.as_str())?;
+        statement.bind(2, self.model_version(source))?;
+        statement.bind(3, text_hash(text).as_str())?;
+        statement.bind(4, self.ttl_secs)?;
+
+        if let State::Row = statement.next()? {
+            let estimates: String = statement.read(0)?;
+            Ok(Some(serde_json::from_str(&estimates)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn upsert(
+        &self,
+        source: &Source,
+        text: &str,
+        estimates: &[Option<f64>],
+    ) -> Result<(), Error> {
+        let mut statement = self.conn.prepare(
+            "INSERT OR REPLACE INTO estimate_cache \
+            (source, model_version, text_hash, estimates) VALUES (?, ?, ?, ?)",
+        )?;
+        statement.bind(1, source.as_str())?;
+        statement.bind(2, self.model_version(source))?;
+        statement.bind(3, text_hash(text).as_str())?;
+        statement.bind(4, serde_json::to_string(estimates)?.as_str())?;
+
+        while !matches!(statement.next()?, State::Done) {
+            //
+        }
+
+        Ok(())
+    }
+
+    /// Deletes entries which can no longer be hit.
+    fn prune(&self) -> Result<(), Error> {
+        for source in &[Source::Dealc, Source::Voucherc, Source::OpenAi] {
+            let mut statement = self.conn.prepare(
+                "DELETE FROM estimate_cache \
+                WHERE source = ? AND model_version != ?",
+            )?;
+            statement.bind(1, source.as_str())?;
+            statement.bind(2, self.model_version(source))?;
+            while !matches!(statement.next()?, State::Done) {
+                //
+            }
+        }
+
+        let mut statement = self.conn.prepare(
+            "DELETE FROM estimate_cache \
+            WHERE created_at < strftime('%s','now') - ?",
+        )?;
+        statement.bind(1, self.ttl_secs)?;
+        while !matches!(statement.next()?, State::Done) {
+            //
+        }
+
+        Ok(())
+    }
+
+    fn model_version(&self, source: &Source) -> &str {
+        match source {
+            Source::Dealc => &self.dealc_model_version,
+            Source::Voucherc => &self.voucherc_model_version,
+            Source::OpenAi => &self.openai_model_version,
+            // local heuristics are cheap, they're not cached, and estimators
+            // added later have no version yet
+            Source::CommonPhrases | Source::Custom(_) => "",
+        }
+    }
+}
+
+fn text_hash(text: &str) -> String {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_caches_estimates_per_model_version() {
+        let dir = std::env::temp_dir()
+            .join(format!("predictor-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.db");
+        let path = path.to_str().unwrap();
+        let mut conf = Conf {
+            dealc_model_version: "1".to_string(),
+            ..Default::default()
+        };
+
+        let cache = Cache::open(path, &conf).unwrap();
+        assert_eq!(cache.get(&Source::Dealc, "20% off"), None);
+        cache.insert(&Source::Dealc, "20% off", &[Some(0.9)]);
+        cache.insert(&Source::OpenAi, "use CODE20", &[None, Some(1.0)]);
+
+        assert_eq!(
+            cache.get(&Source::Dealc, " 20%  off\n"),
+            Some(vec![Some(0.9)])
+        );
+        assert_eq!(cache.get(&Source::Voucherc, "20% off"), None);
+        assert_eq!(
+            cache.get(&Source::OpenAi, "use CODE20"),
+            Some(vec![None, Some(1.0)])
+        );
+        drop(cache);
+
+        // a new model version invalidates its source only
+        conf.dealc_model_version = "2".to_string();
+        let cache = Cache::open(path, &conf).unwrap();
+        assert_eq!(cache.get(&Source::Dealc, "20% off"), None);
+        assert_eq!(
+            cache.get(&Source::OpenAi, "use CODE20"),
+            Some(vec![None, Some(1.0)])
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 //! Name of each env var is the same as the property but in ALL_CAPS.
 
+use crate::estimate::DEFAULT_ESTIMATORS;
 use {serde::Deserialize, shared::rusoto_core::Region};
 
 #[derive(Default, Deserialize, Debug)]
@@ -23,10 +24,69 @@ pub struct Conf {
     pub openai_key: String,
     /// Where can we reach OpenAI servers.
     pub openai_completion_url: String,
+    /// How long we wait for dealc to respond.
+    ///
+    /// # Default
+    /// See [`shared::http::Conf::http_timeout_secs`].
+    pub dealc_timeout_secs: Option<u64>,
+    /// How long we wait for voucherc to respond.
+    ///
+    /// # Default
+    /// See [`shared::http::Conf::http_timeout_secs`].
+    pub voucherc_timeout_secs: Option<u64>,
+    /// How long we wait for OpenAI to respond.
+    ///
+    /// # Default
+    /// See [`shared::http::Conf::http_timeout_secs`].
+    pub openai_timeout_secs: Option<u64>,
+    /// At most how many phrases or words are sent to dealc or voucherc in one
+    /// request. Long newsletters are split into several requests.
+    ///
+    /// # Default
+    /// 256
+    pub classifier_chunk_len: Option<usize>,
+    /// Path to the sqlite3 file which caches the estimates, see
+    /// [`crate::cache`].
+    ///
+    /// # Default
+    /// If not set, nothing is cached.
+    pub estimate_cache_path: Option<String>,
+    /// # Default
+    /// A week.
+    pub estimate_cache_ttl_secs: Option<i64>,
+    /// Cached estimates of other versions are deleted. Change it when the
+    /// model of dealc changes.
+    #[serde(default)]
+    pub dealc_model_version: String,
+    /// See [`Conf::dealc_model_version`].
+    #[serde(default)]
+    pub voucherc_model_version: String,
+    /// See [`Conf::dealc_model_version`].
+    #[serde(default)]
+    pub openai_model_version: String,
+    /// Comma separated names of the estimators to run, in order. See
+    /// [`crate::estimate::registry`] for the names.
+    ///
+    /// # Default
+    /// [`crate::estimate::DEFAULT_ESTIMATORS`]
+    pub estimators: Option<Vec<String>>,
     /// Where the output predictions are stored in json.
     pub prediction_bucket_name: String,
 }
 
+impl Conf {
+    pub fn classifier_chunk_len(&self) -> usize {
+        self.classifier_chunk_len.unwrap_or(256).max(1)
+    }
+
+    pub fn estimators(&self) -> Vec<&str> {
+        match &self.estimators {
+            Some(names) => names.iter().map(String::as_str).collect(),
+            None => DEFAULT_ESTIMATORS.to_vec(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,6 +101,7 @@ mod tests {
         env::set_var("OPENAI_KEY", "abckey");
         env::set_var("OPENAI_COMPLETION_URL", "url");
         env::set_var("PREDICTION_BUCKET_NAME", "bucket");
+        env::set_var("ESTIMATORS", "dealc,voucherc");
 
         let conf = envy::from_env::<Conf>().unwrap();
 
@@ -51,5 +112,6 @@ mod tests {
         assert_eq!(conf.openai_key, "abckey");
         assert_eq!(conf.openai_completion_url, "url");
         assert_eq!(conf.prediction_bucket_name, "bucket");
+        assert_eq!(conf.estimators(), vec!["dealc", "voucherc"]);
     }
 }
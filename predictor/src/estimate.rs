@@ -0,0 +1,120 @@
+//! Each signal about how likely a phrase is a deal or a word is a voucher is an
+//! [`Estimator`]. The estimators which the predictor runs, and their order, are
+//! configured with the `ESTIMATORS` env var, see [`registry`].
+//!
+//! Estimators run concurrently, unless an estimator [reads the
+//! estimates][Estimator::reads_estimates] of the estimators before it. Then it
+//! waits for them.
+//!
+//! A new signal implements the trait, has its own [`Source`] name, which can be
+//! [`Source::Custom`], and is added to the [`registry`].
+
+use crate::{
+    cache::Cache,
+    predict::{classifier::Classifier, common_phrases::CommonPhrases, openai},
+    prelude::*,
+};
+use async_trait::async_trait;
+use shared::{
+    document::{Document, Source},
+    http,
+};
+
+/// The estimators which run when `ESTIMATORS` isn't set.
+pub const DEFAULT_ESTIMATORS: &[&str] =
+    &["dealc", "voucherc", "common_phrases", "open_ai"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// One estimate per phrase of the document.
+    Phrase,
+    /// One estimate per word of the document, words of all phrases in order.
+    Word,
+}
+
+/// What the estimators share.
+pub struct Context<'a> {
+    pub conf: &'a Conf,
+    pub http_client: &'a dyn http::Client,
+    pub cache: Option<&'a Cache>,
+}
+
+#[async_trait(?Send)]
+pub trait Estimator {
+    /// Under which name the estimates are stored in the document.
+    fn source(&self) -> Source;
+
+    fn level(&self) -> Level;
+
+    /// Whether the estimator looks at the estimates which the estimators
+    /// before it stored in the document.
+    fn reads_estimates(&self) -> bool {
+        false
+    }
+
+    /// Must return exactly one estimate per phrase or word, depending on the
+    /// [`Level`]. [`None`] if the estimator has no opinion.
+    async fn estimate(
+        &self,
+        ctx: &Context<'_>,
+        document: &Document,
+    ) -> Result<Vec<Option<f64>>, Error>;
+}
+
+/// Constructs the estimators named in [`Conf::estimators`], in that order.
+pub fn registry(conf: &Conf) -> Result<Vec<Box<dyn Estimator>>, Error> {
+    conf.estimators()
+        .into_iter()
+        .map(|name| -> Result<Box<dyn Estimator>, Error> {
+            match name {
+                "dealc" => Ok(Box::new(Classifier::new(
+                    Source::Dealc,
+                    Level::Phrase,
+                    &conf.dealc_url,
+                    conf.classifier_chunk_len(),
+                ))),
+                "voucherc" => Ok(Box::new(Classifier::new(
+                    Source::Voucherc,
+                    Level::Word,
+                    &conf.voucherc_url,
+                    conf.classifier_chunk_len(),
+                ))),
+                "common_phrases" => Ok(Box::new(CommonPhrases)),
+                "open_ai" => Ok(Box::new(openai::OpenAi)),
+                _ => Err(Error::fatal(format!("Unknown estimator {}", name))
+                    .with_code("conf")),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_registry_in_order() {
+        let conf = Conf {
+            estimators: Some(vec![
+                "voucherc".to_string(),
+                "open_ai".to_string(),
+            ]),
+            ..Default::default()
+        };
+
+        let sources: Vec<_> = registry(&conf)
+            .unwrap()
+            .iter()
+            .map(|e| e.source())
+            .collect();
+        assert_eq!(sources, vec![Source::Voucherc, Source::OpenAi]);
+
+        assert_eq!(registry(&Conf::default()).unwrap().len(), 4);
+
+        let conf = Conf {
+            estimators: Some(vec!["crystal_ball".to_string()]),
+            ..Default::default()
+        };
+        assert!(registry(&conf).is_err());
+    }
+}
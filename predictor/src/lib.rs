@@ -0,0 +1,142 @@
+pub mod cache;
+pub mod conf;
+pub mod error;
+pub mod estimate;
+mod predict;
+pub mod prelude;
+pub mod state;
+
+use async_trait::async_trait;
+use futures::future;
+use prelude::*;
+use shared::{
+    http,
+    reqwest::{self, header},
+    s3::{NewS3Object, PutConf},
+    server::Readiness,
+    vision::Annotation,
+    worker::Handler,
+};
+use state::State;
+use std::time::Duration;
+
+/// The readiness probe must answer within the request timeout of
+/// [`shared::server`].
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[async_trait(?Send)]
+impl Handler for State {
+    type Error = Error;
+
+    async fn handle(&self, record: NewS3Object) -> Result<(), Error> {
+        handle(self, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Readiness for State {
+    /// The classifiers answer an empty list of phrases with an empty list.
+    async fn check(&self) -> Result<(), Error> {
+        let empty = serde_json::json!([]);
+        let probes = [&self.conf.dealc_url, &self.conf.voucherc_url]
+            .iter()
+            .map(|url| http::Client::post_json(&self.probe_client, url, &empty))
+            .collect::<Vec<_>>();
+        future::try_join_all(probes).await?;
+
+        Ok(())
+    }
+}
+
+/// Builds a client which authenticates requests to OpenAI with the given key.
+pub fn http_client(openai_key: &str) -> Result<reqwest::Client, Error> {
+    let mut headers = header::HeaderMap::new();
+    let mut auth_value =
+        header::HeaderValue::from_str(&format!("Bearer {}", openai_key))
+            .expect("Invalid openai key characters");
+    auth_value.set_sensitive(true);
+    headers.insert(header::AUTHORIZATION, auth_value);
+
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+/// Plain client with a short timeout for the readiness probe.
+pub fn probe_client() -> Result<reqwest::Client, Error> {
+    Ok(reqwest::Client::builder().timeout(PROBE_TIMEOUT).build()?)
+}
+
+/// Wraps [`http_client`] with retries, circuit breakers and the timeout of each
+/// endpoint.
+pub fn retrying_http_client(
+    conf: &Conf,
+    http_conf: http::Conf,
+) -> Result<http::Retrying<reqwest::Client>, Error> {
+    let timeouts = vec![
+        (&conf.dealc_url, conf.dealc_timeout_secs),
+        (&conf.voucherc_url, conf.voucherc_timeout_secs),
+        (&conf.openai_completion_url, conf.openai_timeout_secs),
+    ];
+
+    let mut client =
+        http::Retrying::new(http_client(&conf.openai_key)?, http_conf);
+    for (url, timeout_secs) in timeouts {
+        if let Some(secs) = timeout_secs {
+            client = client.with_timeout(url, Duration::from_secs(secs));
+        }
+    }
+
+    Ok(client)
+}
+
+/// 1. Load OCR output from S3 bucket.
+///
+/// 2. Use various methods to predict what are vouchers and what are deals.
+///
+/// 3. Store the result into an S3 bucket.
+pub async fn handle(state: &State, record: NewS3Object) -> Result<(), Error> {
+    // 1.
+    let body = state
+        .s3
+        .get(record.bucket, record.key.clone())
+        .await?
+        .ok_or_else(|| {
+            Error::new("OCR objects cannot have empty body")
+                .with_code("empty_object")
+        })?;
+    let annotation: Annotation = serde_json::from_slice(&body)?;
+
+    // 2.
+    let ctx = estimate::Context {
+        conf: &state.conf,
+        http_client: state.http_client.as_ref(),
+        cache: state.cache.as_ref(),
+    };
+    let document =
+        predict::deals_and_vouchers(&ctx, &state.estimators, &annotation)
+            .await?;
+    let document = serde_json::to_string(&document)?;
+
+    // 3.
+    state
+        .s3
+        .put(
+            state.conf.prediction_bucket_name.clone(),
+            record.key.clone(),
+            document.into_bytes(),
+            PutConf {
+                content_type: Some("application/json".to_string()),
+                ..Default::default()
+            }
+            .with_trace_id(&record.key),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    //
+}
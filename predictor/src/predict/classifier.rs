@@ -0,0 +1,221 @@
+use crate::{
+    cache::Cache,
+    estimate::{Context, Estimator, Level},
+    prelude::*,
+};
+use async_trait::async_trait;
+use futures::{stream, StreamExt, TryStreamExt};
+use shared::{
+    document::{Document, Source},
+    http, metrics,
+};
+
+/// How many chunks of one classifier's input are in flight at once.
+const MAX_CONCURRENT_CHUNKS: usize = 4;
+
+/// A service which takes a list of phrases or words and returns one estimate
+/// for each, such as dealc and voucherc.
+pub struct Classifier {
+    source: Source,
+    level: Level,
+    url: String,
+    /// See [`Conf::classifier_chunk_len`].
+    chunk_len: usize,
+}
+
+#[async_trait(?Send)]
+impl Estimator for Classifier {
+    fn source(&self) -> Source {
+        self.source.clone()
+    }
+
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    async fn estimate(
+        &self,
+        ctx: &Context<'_>,
+        document: &Document,
+    ) -> Result<Vec<Option<f64>>, Error> {
+        let inputs = match self.level {
+            Level::Phrase => document.phrases_str(),
+            Level::Word => document.words_str(),
+        };
+
+        self.classify(ctx.http_client, ctx.cache, &inputs).await
+    }
+}
+
+impl Classifier {
+    pub fn new(
+        source: Source,
+        level: Level,
+        url: impl Into<String>,
+        chunk_len: usize,
+    ) -> Self {
+        Self {
+            source,
+            level,
+            url: url.into(),
+            chunk_len,
+        }
+    }
+
+    /// Looks up the estimates of each input in the cache and posts the rest to
+    /// the classifier in chunks. The estimates are returned in the order of the
+    /// inputs.
+    async fn classify(
+        &self,
+        http_client: &dyn http::Client,
+        cache: Option<&Cache>,
+        inputs: &[&str],
+    ) -> Result<Vec<Option<f64>>, Error> {
+        let mut estimates: Vec<_> = inputs
+            .iter()
+            .map(|input| {
+                cache
+                    .and_then(|cache| cache.get(&self.source, input))
+                    .and_then(|cached| cached.first().copied().flatten())
+            })
+            .collect();
+        let misses: Vec<_> = (0..inputs.len())
+            .filter(|index| estimates[*index].is_none())
+            .collect();
+        let miss_inputs: Vec<_> =
+            misses.iter().map(|index| inputs[*index]).collect();
+
+        let fetched = self.post_in_chunks(http_client, &miss_inputs).await?;
+        if fetched.len() != miss_inputs.len() {
+            return Err(Error::new(format!(
+                "Sent {} inputs to {}, but got {} estimates",
+                miss_inputs.len(),
+                self.source.as_str(),
+                fetched.len()
+            ))
+            .with_code("estimate_count"));
+        }
+
+        for (index, estimate) in misses.into_iter().zip(fetched) {
+            if let Some(cache) = cache {
+                cache.insert(&self.source, inputs[index], &[Some(estimate)]);
+            }
+            estimates[index] = Some(estimate);
+        }
+
+        Ok(estimates)
+    }
+
+    /// Concatenates the estimates in the order of the chunks.
+    async fn post_in_chunks(
+        &self,
+        http_client: &dyn http::Client,
+        inputs: &[&str],
+    ) -> Result<Vec<f64>, Error> {
+        let requests = inputs.chunks(self.chunk_len).map(|chunk| async move {
+            let body = serde_json::to_value(chunk)?;
+            let res_body = metrics::time(
+                self.source.as_str(),
+                http_client.post_json(&self.url, &body),
+            )
+            .await?;
+            Ok::<_, Error>(serde_json::from_slice::<Vec<f64>>(&res_body)?)
+        });
+
+        let chunks: Vec<_> = stream::iter(requests)
+            .buffered(MAX_CONCURRENT_CHUNKS)
+            .try_collect()
+            .await?;
+
+        Ok(chunks.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_classifies_in_chunks() {
+        let classifier =
+            Classifier::new(Source::Dealc, Level::Phrase, "dealc", 2);
+        let http_client = ClassifierStub::default();
+
+        let estimates = classifier
+            .classify(&http_client, None, &["a", "bb", "ccc", "dddd", "eeeee"])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            estimates,
+            vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]
+        );
+        assert_eq!(*http_client.requests.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_classifies_only_uncached_inputs() {
+        let classifier =
+            Classifier::new(Source::Voucherc, Level::Word, "voucherc", 10);
+        let cache = Cache::open(":memory:", &Conf::default()).unwrap();
+        let http_client = ClassifierStub::default();
+
+        classifier
+            .classify(&http_client, Some(&cache), &["a", "bb"])
+            .await
+            .unwrap();
+        let estimates = classifier
+            .classify(&http_client, Some(&cache), &["a", "ccc", "bb"])
+            .await
+            .unwrap();
+
+        assert_eq!(estimates, vec![Some(1.0), Some(3.0), Some(2.0)]);
+        assert_eq!(*http_client.requests.lock().unwrap(), 2);
+        assert_eq!(cache.get(&Source::Voucherc, "ccc"), Some(vec![Some(3.0)]));
+    }
+
+    #[tokio::test]
+    async fn it_errs_on_wrong_number_of_estimates() {
+        let classifier =
+            Classifier::new(Source::Voucherc, Level::Word, "voucherc", 1);
+        let http_client = ClassifierStub {
+            skip_last: true,
+            ..Default::default()
+        };
+
+        let e = classifier
+            .classify(&http_client, None, &["use", "code"])
+            .await
+            .unwrap_err();
+
+        assert_eq!(e.code(), "malformed_input.estimate_count");
+    }
+
+    /// Estimates each input by its length.
+    #[derive(Default)]
+    struct ClassifierStub {
+        requests: std::sync::Mutex<usize>,
+        /// Returns one estimate less than there are inputs.
+        skip_last: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl http::Client for ClassifierStub {
+        async fn post_json(
+            &self,
+            _url: &str,
+            body: &serde_json::Value,
+        ) -> Result<Vec<u8>, Error> {
+            *self.requests.lock().unwrap() += 1;
+            let inputs: Vec<String> = serde_json::from_value(body.clone())?;
+            let mut estimates: Vec<f64> =
+                inputs.iter().map(|i| i.len() as f64).collect();
+            if self.skip_last {
+                estimates.pop();
+            }
+
+            Ok(serde_json::to_vec(&estimates)?)
+        }
+    }
+}
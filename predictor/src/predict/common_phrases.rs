@@ -1,4 +1,9 @@
-use shared::document::Word;
+use crate::{
+    estimate::{Context, Estimator, Level},
+    prelude::*,
+};
+use async_trait::async_trait;
+use shared::document::{Document, Source, Word};
 
 const VOUCHER_KEYWORDS: &[&str] = &["voucher", "code", "discount", "coupon"];
 
@@ -51,6 +56,34 @@ struct CommonPhraseTracker {
 
 const ESTIMATE_FOR_NOT_MATCHED_WORDS: f64 = 0.25;
 
+/// Common newsletter phrases such as "USE CODE ABC20".
+pub struct CommonPhrases;
+
+#[async_trait(?Send)]
+impl Estimator for CommonPhrases {
+    fn source(&self) -> Source {
+        Source::CommonPhrases
+    }
+
+    fn level(&self) -> Level {
+        Level::Word
+    }
+
+    async fn estimate(
+        &self,
+        _ctx: &Context<'_>,
+        document: &Document,
+    ) -> Result<Vec<Option<f64>>, Error> {
+        let words: Vec<_> =
+            document.phrases().iter().flat_map(|p| &p.words).collect();
+
+        Ok(match word_estimates(&words) {
+            Some(estimates) => estimates.into_iter().map(Some).collect(),
+            None => words.iter().map(|_| None).collect(),
+        })
+    }
+}
+
 pub fn word_estimates(words: &[&Word]) -> Option<Vec<f64>> {
     let a = over_special_chars(words);
     let b = over_long_phrases(words.iter().map(|w| w.text.as_str()));
@@ -434,6 +467,7 @@ mod tests {
             raw: s.to_string(),
             text: s.to_string(),
             estimates: Default::default(),
+            confidence: None,
         }
     }
 }
@@ -1,73 +1,62 @@
-mod common_phrases;
-mod openai;
+pub mod classifier;
+pub mod common_phrases;
+pub mod openai;
 
-use crate::prelude::*;
-use shared::http;
+use crate::{
+    estimate::{Context, Estimator, Level},
+    prelude::*,
+};
+use futures::future::try_join_all;
 use shared::{
     document::{Document, Source},
     vision::Annotation,
 };
 
+/// Runs the estimators in stages. A stage ends before an estimator which reads
+/// estimates, so that it sees the estimates of all estimators before it. The
+/// estimators within a stage run concurrently.
 pub async fn deals_and_vouchers(
-    conf: &Conf,
-    http_client: &dyn http::Client,
+    ctx: &Context<'_>,
+    estimators: &[Box<dyn Estimator>],
     annotation: &Annotation,
 ) -> Result<Document, Error> {
     let mut document = Document::from_ocr(annotation);
 
-    apply_dealc_and_voucherc_estimates(conf, http_client, &mut document)
+    let mut rest = estimators;
+    while !rest.is_empty() {
+        let stage_len = 1 + rest[1..]
+            .iter()
+            .take_while(|estimator| !estimator.reads_estimates())
+            .count();
+        let (stage, next) = rest.split_at(stage_len);
+
+        let stage_estimates = try_join_all(
+            stage
+                .iter()
+                .map(|estimator| estimator.estimate(ctx, &document)),
+        )
         .await?;
+        for (estimator, estimates) in stage.iter().zip(stage_estimates) {
+            match estimator.level() {
+                Level::Phrase => apply_phrases_estimates(
+                    &mut document,
+                    estimator.source(),
+                    estimates,
+                )?,
+                Level::Word => apply_words_estimates(
+                    &mut document,
+                    estimator.source(),
+                    estimates,
+                )?,
+            }
+        }
 
-    // if there are some some common newsletter phrases (USE CODE ABC20), then
-    // apply estimates from those
-    let common_phrases_estimates =
-        common_phrases::word_estimates(document.words().as_slice());
-    if let Some(estimates) = common_phrases_estimates {
-        let estimates = estimates.into_iter().map(Some).collect();
-        apply_words_estimates(&mut document, Source::CommonPhrases, estimates)?;
+        rest = next;
     }
 
-    // send some promising phrases to openai to check them out
-    let openai_estimates =
-        openai::word_estimates(conf, http_client, document.phrases()).await;
-    apply_words_estimates(&mut document, Source::OpenAi, openai_estimates)?;
-
     Ok(document)
 }
 
-async fn apply_dealc_and_voucherc_estimates(
-    conf: &Conf,
-    http_client: &dyn http::Client,
-    document: &mut Document,
-) -> Result<(), Error> {
-    // fetch estimates for how likely each phrase is a deal
-    // TODO: can be made concurrent with next step
-    let dealc_estimates: Vec<_> = {
-        let phrases_json = serde_json::to_value(&document.phrases_str())?;
-        let dealc_res_body = http_client
-            .post_json(&conf.dealc_url, &phrases_json)
-            .await?;
-
-        let estimates: Vec<f64> = serde_json::from_slice(&dealc_res_body)?;
-        estimates.into_iter().map(Some).collect()
-    };
-    apply_phrases_estimates(document, Source::Dealc, dealc_estimates)?;
-
-    // fetch estimates for how likely each word is a voucher
-    let voucherc_estimates: Vec<_> = {
-        let words_json = serde_json::to_value(document.words_str())?;
-        let voucherc_res_body = http_client
-            .post_json(&conf.voucherc_url, &words_json)
-            .await?;
-
-        let estimates: Vec<f64> = serde_json::from_slice(&voucherc_res_body)?;
-        estimates.into_iter().map(Some).collect()
-    };
-    apply_words_estimates(document, Source::Voucherc, voucherc_estimates)?;
-
-    Ok(())
-}
-
 pub fn apply_phrases_estimates(
     document: &mut Document,
     source: Source,
@@ -79,7 +68,8 @@ pub fn apply_phrases_estimates(
             "Got {} phrases, but {} estimates",
             phrases.len(),
             estimates.len()
-        )));
+        ))
+        .with_code("estimate_count"));
     }
 
     for (phrase, estimate) in phrases.into_iter().zip(estimates.into_iter()) {
@@ -102,7 +92,8 @@ pub fn apply_words_estimates(
             "Got {} words, but {} estimates",
             words.len(),
             estimates.len()
-        )));
+        ))
+        .with_code("estimate_count"));
     }
 
     for (w, estimate) in words.into_iter().zip(estimates.into_iter()) {
@@ -131,20 +122,128 @@ mod tests {
         let conf = Conf {
             dealc_url: "http://localhost:8081".to_string(),
             voucherc_url: "http://localhost:8080".to_string(),
+            estimators: Some(vec!["dealc".to_string(), "voucherc".to_string()]),
             ..Default::default()
         };
         let http_client = reqwest::Client::new();
+        let ctx = Context {
+            conf: &conf,
+            http_client: &http_client,
+            cache: None,
+        };
         let contents = fs::read_to_string(ocr_path).unwrap();
         let annotation = serde_json::from_str(&contents).unwrap();
-        let mut phrases = Document::from_ocr(&annotation);
 
-        apply_dealc_and_voucherc_estimates(&conf, &http_client, &mut phrases)
-            .await
-            .expect("Cannot get phrases with estimates");
+        let phrases = deals_and_vouchers(
+            &ctx,
+            &crate::estimate::registry(&conf).unwrap(),
+            &annotation,
+        )
+        .await
+        .expect("Cannot get phrases with estimates");
 
         panic!("{:#?}", phrases);
     }
 
+    #[tokio::test]
+    async fn it_runs_estimators_in_stages() {
+        let conf = Conf::default();
+        let http_client = reqwest::Client::new();
+        let ctx = Context {
+            conf: &conf,
+            http_client: &http_client,
+            cache: None,
+        };
+        let estimators: Vec<Box<dyn Estimator>> = vec![
+            Box::new(EstimatorStub::new("a", Level::Phrase, false)),
+            Box::new(EstimatorStub::new("b", Level::Word, false)),
+            Box::new(EstimatorStub::new("c", Level::Word, true)),
+        ];
+        let annotation = serde_json::from_value(json!({
+            "text": "use code",
+            "words": [
+                { "tl": {"x": 0, "y": 0}, "br": {"x": 0, "y": 0}, "w": "use" },
+                { "tl": {"x": 0, "y": 0}, "br": {"x": 0, "y": 0}, "w": "code" },
+            ]
+        }))
+        .unwrap();
+
+        let document = deals_and_vouchers(&ctx, &estimators, &annotation)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            json!([{
+                "text": "use code",
+                "estimates": { "a": 0.0 },
+                "words": [
+                    { "text": "use", "estimates": { "b": 0.0, "c": 2.0 } },
+                    { "text": "code", "estimates": { "b": 0.0, "c": 2.0 } },
+                ]
+            }, {
+                "text": "<br>",
+                "estimates": { "a": 0.0 },
+                "words": [
+                    { "text": "<br>", "estimates": { "b": 0.0, "c": 2.0 } },
+                ]
+            }]),
+            serde_json::to_value(&document).unwrap()
+        );
+    }
+
+    /// Estimates each phrase or word by the number of estimates it already has,
+    /// therefore estimators in the same stage estimate zero.
+    struct EstimatorStub {
+        source: Source,
+        level: Level,
+        reads_estimates: bool,
+    }
+
+    impl EstimatorStub {
+        fn new(name: &str, level: Level, reads_estimates: bool) -> Self {
+            Self {
+                source: Source::Custom(name.to_string()),
+                level,
+                reads_estimates,
+            }
+        }
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl Estimator for EstimatorStub {
+        fn source(&self) -> Source {
+            self.source.clone()
+        }
+
+        fn level(&self) -> Level {
+            self.level
+        }
+
+        fn reads_estimates(&self) -> bool {
+            self.reads_estimates
+        }
+
+        async fn estimate(
+            &self,
+            _ctx: &Context<'_>,
+            document: &Document,
+        ) -> Result<Vec<Option<f64>>, Error> {
+            let phrases = document.phrases();
+            let phrase_estimates =
+                phrases.iter().map(|p| Some(p.estimates.len() as f64));
+            let word_estimates = phrases.iter().flat_map(|p| {
+                p.words.iter().map(move |w| {
+                    Some((p.estimates.len() + w.estimates.len()) as f64)
+                })
+            });
+
+            Ok(match self.level {
+                Level::Phrase => phrase_estimates.collect(),
+                Level::Word => word_estimates.collect(),
+            })
+        }
+    }
+
     #[test]
     fn it_serializes() {
         let json = json!({
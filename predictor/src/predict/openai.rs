@@ -1,10 +1,15 @@
-use crate::prelude::*;
+use crate::{
+    cache::Cache,
+    estimate::{Context, Estimator, Level},
+    prelude::*,
+};
+use async_trait::async_trait;
 use futures::future::join_all;
 use serde::Deserialize;
 use serde_json::json;
 use shared::{
-    document::{self, Phrase},
-    http,
+    document::{self, Document, Phrase, Source},
+    http, metrics,
 };
 
 #[derive(Debug, Deserialize)]
@@ -16,33 +21,96 @@ struct OpenAiResponseChoice {
     text: String,
 }
 
+/// Sends the phrases which other estimators deem promising to OpenAI to find
+/// the voucher in them.
+pub struct OpenAi;
+
+#[async_trait(?Send)]
+impl Estimator for OpenAi {
+    fn source(&self) -> Source {
+        Source::OpenAi
+    }
+
+    fn level(&self) -> Level {
+        Level::Word
+    }
+
+    fn reads_estimates(&self) -> bool {
+        true
+    }
+
+    async fn estimate(
+        &self,
+        ctx: &Context<'_>,
+        document: &Document,
+    ) -> Result<Vec<Option<f64>>, Error> {
+        Ok(word_estimates(
+            ctx.conf,
+            ctx.http_client,
+            ctx.cache,
+            document.phrases(),
+        )
+        .await)
+    }
+}
+
 pub async fn word_estimates(
     conf: &Conf,
     http_client: &dyn http::Client,
+    cache: Option<&Cache>,
     phrases: &[Phrase],
 ) -> Vec<Option<f64>> {
-    let jobs = phrases.iter().map(|p| process_phrase(conf, http_client, p));
+    // OpenAI is only a hint, we don't fail the message nor wait for it to
+    // recover
+    if !http_client.is_available(&conf.openai_completion_url) {
+        log::warn!("OpenAI is unavailable, skipping its estimates");
+        return phrases
+            .iter()
+            .flat_map(|p| p.words.iter().map(|_| None))
+            .collect();
+    }
+
+    let jobs = phrases
+        .iter()
+        .map(|p| process_phrase(conf, http_client, cache, p));
     join_all(jobs).await.into_iter().flatten().collect()
 }
 
 async fn process_phrase(
     conf: &Conf,
     http_client: &dyn http::Client,
+    cache: Option<&Cache>,
     p: &Phrase,
 ) -> Vec<Option<f64>> {
     let should_search = p.avg_estimate() > 0.7;
-    if should_search {
-        search_phrase(conf, http_client, p).await
-    } else {
-        p.words.iter().map(|_| None).collect()
+    if !should_search {
+        return p.words.iter().map(|_| None).collect();
+    }
+
+    let cached = cache
+        .and_then(|cache| cache.get(&Source::OpenAi, &p.text))
+        .filter(|estimates| estimates.len() == p.words.len());
+    if let Some(estimates) = cached {
+        return estimates;
+    }
+
+    match search_phrase(conf, http_client, p).await {
+        Some(estimates) => {
+            if let Some(cache) = cache {
+                cache.insert(&Source::OpenAi, &p.text, &estimates);
+            }
+            estimates
+        }
+        None => p.words.iter().map(|_| None).collect(),
     }
 }
 
+/// Returns [`None`] if the request failed.
 async fn search_phrase(
     conf: &Conf,
     http_client: &dyn http::Client,
     p: &Phrase,
-) -> Vec<Option<f64>> {
+) -> Option<Vec<Option<f64>>> {
     // https://beta.openai.com/docs/api-reference/completions/create
     let req = json!({
       "prompt": format!("{}\n\nUse code:", p.text),
@@ -52,27 +120,27 @@ async fn search_phrase(
       "frequency_penalty": 0, // we want the same token returned
     });
 
-    let first_word = http_client
-        .post_json(&conf.openai_completion_url, &req)
-        .await
-        .map_err(Error::from)
-        .and_then(|bytes| {
-            serde_json::from_slice::<OpenAiResponseBody>(&bytes)
-                .map_err(Error::from)
-        })
-        .and_then(|json| {
-            json.choices
-                .into_iter()
-                .next()
-                .ok_or_else(|| Error::new("Empty response from OpenAI"))
-        })
-        .map(|choice| document::words::from_phrase(&choice.text))
-        .map_err(|e| {
-            log::error!("Cannot perform OpenAI request due to {}", e);
-            e
+    let words = metrics::time(
+        "openai",
+        http_client.post_json(&conf.openai_completion_url, &req),
+    )
+    .await
+    .and_then(|bytes| {
+        serde_json::from_slice::<OpenAiResponseBody>(&bytes)
+            .map_err(Error::from)
+    })
+    .and_then(|json| {
+        json.choices.into_iter().next().ok_or_else(|| {
+            Error::new("Empty response from OpenAI").with_code("empty_response")
         })
-        .ok()
-        .and_then(|words| words.into_iter().next());
+    })
+    .map(|choice| document::words::from_phrase(&choice.text))
+    .map_err(|e| {
+        log::error!("Cannot perform OpenAI request due to {}", e);
+        e
+    })
+    .ok()?;
+    let first_word = words.into_iter().next();
 
     // usually GPT-3 returns the voucher as the first word or rubbish if no
     // voucher
@@ -92,21 +160,17 @@ async fn search_phrase(
             .collect();
 
         if any_match {
-            return estimates;
+            return Some(estimates);
         }
     }
 
-    p.words.iter().map(|_| None).collect()
+    Some(p.words.iter().map(|_| None).collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use shared::{
-        document::Source,
-        reqwest::{self, header},
-        Document,
-    };
+    use shared::reqwest::{self, header};
     use std::env;
     use tokio::fs;
 
@@ -116,7 +180,10 @@ mod tests {
         let (conf, http_client) = make_openai_state();
 
         // 1. empty input results in empty output
-        assert_eq!(word_estimates(&conf, &http_client, &[]).await, vec![]);
+        assert_eq!(
+            word_estimates(&conf, &http_client, None, &[]).await,
+            vec![]
+        );
 
         // 2. read testing file into document
         let contents =
@@ -134,7 +201,7 @@ mod tests {
         .unwrap();
 
         let _estimates =
-            word_estimates(&conf, &http_client, document.phrases()).await;
+            word_estimates(&conf, &http_client, None, document.phrases()).await;
     }
 
     fn make_openai_state() -> (Conf, reqwest::Client) {
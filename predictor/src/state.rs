@@ -1,9 +1,16 @@
-use crate::prelude::*;
-use shared::{http, S3Ext, SqsExt};
+use crate::{cache::Cache, estimate::Estimator, prelude::*};
+use shared::{http, reqwest, S3Ext};
 
 pub struct State {
     pub conf: Conf,
-    pub sqs: Box<dyn SqsExt>,
     pub s3: Box<dyn S3Ext>,
     pub http_client: Box<dyn http::Client>,
+    /// Built by [`crate::probe_client`], it doesn't retry so that the
+    /// readiness probe answers quickly and doesn't trip the circuit breakers
+    /// of [`Self::http_client`].
+    pub probe_client: reqwest::Client,
+    /// See [`crate::cache`].
+    pub cache: Option<Cache>,
+    /// Built from [`Conf::estimators`] by [`crate::estimate::registry`].
+    pub estimators: Vec<Box<dyn Estimator>>,
 }
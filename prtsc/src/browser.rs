@@ -1,4 +1,4 @@
-use crate::prelude::*;
+use crate::{error, prelude::*};
 use async_trait::async_trait;
 use image::{io::Reader as ImageReader, ImageFormat, ImageOutputFormat};
 use shared::anchor::Anchor;
@@ -41,7 +41,7 @@ pub async fn connect(gecko_url: &str) -> Result<fantoccini::Client, Error> {
         .await
         .map_err(|e| {
             log::error!("Cannot start gecko client: {}", e);
-            Error::fatal(e)
+            error::browser(e)
         })
 }
 
@@ -51,6 +51,9 @@ pub trait Headless {
         &mut self,
         url: &str,
     ) -> Result<(Vec<u8>, Vec<Anchor>), Error>;
+
+    /// Errors if the driver no longer responds or the session broke.
+    async fn check_session(&mut self) -> Result<(), Error>;
 }
 
 #[async_trait]
@@ -59,20 +62,29 @@ impl Headless for fantoccini::Client {
         &mut self,
         url: &str,
     ) -> Result<(Vec<u8>, Vec<Anchor>), Error> {
-        self.goto(url).await?;
+        self.goto(url).await.map_err(error::browser)?;
         log::trace!("Navigated to {}, taking screenshot now", url);
 
         let anchors: Vec<Anchor> = serde_json::from_value(
-            self.execute(SELECT_ANCHORS_SCRIPT_JS, vec![]).await?,
+            self.execute(SELECT_ANCHORS_SCRIPT_JS, vec![])
+                .await
+                .map_err(error::browser)?,
         )?;
 
-        let png = self.screenshot().await?;
+        let png = self.screenshot().await.map_err(error::browser)?;
         let img = ImageReader::with_format(Cursor::new(png), ImageFormat::Png)
-            .decode()?;
+            .decode()
+            .map_err(error::image)?;
         let mut jpeg: Vec<u8> = Vec::new();
-        img.write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))?;
+        img.write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .map_err(error::image)?;
         Ok((jpeg, anchors))
     }
+
+    async fn check_session(&mut self) -> Result<(), Error> {
+        self.current_url().await.map_err(error::browser)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -2,6 +2,7 @@
 
 use serde::Deserialize;
 use shared::rusoto_core::Region;
+use std::time::Duration;
 
 #[derive(Default, Deserialize, Debug)]
 pub struct Conf {
@@ -28,6 +29,18 @@ pub struct Conf {
     /// reference them later with found deals and vouchers. This is the name of
     /// the bucket where we store those links.
     pub anchor_bucket_name: String,
+    /// For how long is the presigned url of the html file valid. The browser
+    /// only needs it to load the page.
+    ///
+    /// # Default
+    /// 5 minutes
+    pub presigned_url_ttl_secs: Option<u64>,
+}
+
+impl Conf {
+    pub fn presigned_url_ttl(&self) -> Duration {
+        Duration::from_secs(self.presigned_url_ttl_secs.unwrap_or(5 * 60))
+    }
 }
 
 fn default_max_screenshot_size() -> usize {
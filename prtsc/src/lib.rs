@@ -0,0 +1,236 @@
+//! `prtsc` is a microservice which listens to SQS messages created by insertion
+//! into an S3 bucket _IN_. _IN_ persists html files which are the contents of
+//! received newsletter. It's setup in such a way that insertion pushes to SQS.
+//!
+//! `prtsc` [`handle`]s each message by capturing a screenshot and uploading it
+//! to another S3 bucket _OUT_. _OUT_ persists the screenshot. The action of
+//! insertion of a screenshot in the _OUT_ S3 publishes a new SQS message, which
+//! is handled the next service in this pipeline.
+//!
+//! Another important job it has is to find all anchors (<a href>) on the page,
+//! capture their bounding boxes and the links they point to and store this info
+//! in an S3 bucket.  Later, we refer to the bounding boxes when we know likely
+//! vouchers and deals, and export data about what link a deal is available at.
+//!
+//! # Concurrency
+//! `prtsc` handles at most one message at a time. Each message contains a
+//! record about exactly one newly inserted html file.
+//!
+//! Unlike the I/O bound services, which can set the worker's `CONCURRENCY`,
+//! `prtsc` drives a single browser session. Therefore we leave the concurrency
+//! at its default of one and instead spawn multiple replicas as necessary.
+//!
+//! # Gecko
+//! To take screenshots, we rely on [geckodriver][gecko]. When this binary is
+//! put into a container, the driver runs as a background process (along with
+//! Xvfb for monitor simulation). While usually the container ethos is to have
+//! one service per container, we are running 3 services in one container!
+//!
+//! The reason for this is error handling. I observed that sometimes the session
+//! that runs in the driver breaks (starts returning errors), or that Xvfb
+//! crashes. When something crashes, this binary will know about the error.
+//! However, the containers running the driver etc don't restart when this
+//! happens. Therefore, when everything is in one container and an error occurs,
+//! we restart everything together.
+//!
+//! To not wait for a message to fail, `/readyz` checks the browser session and
+//! k8s restarts the container if the check keeps failing.
+//!
+//! [gecko]: https://github.com/mozilla/geckodriver/releases
+
+pub mod browser;
+pub mod conf;
+pub mod error;
+pub mod prelude;
+pub mod state;
+
+use async_trait::async_trait;
+use prelude::*;
+use shared::metrics;
+use shared::s3::NewS3Object;
+use shared::server::Readiness;
+use shared::worker::Handler;
+use state::State;
+
+#[async_trait(?Send)]
+impl Handler for State {
+    type Error = Error;
+
+    async fn handle(&self, record: NewS3Object) -> Result<(), Error> {
+        handle(self, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Readiness for State {
+    async fn check(&self) -> Result<(), Error> {
+        match self.browser.try_lock() {
+            Ok(mut browser) => browser.check_session().await,
+            // the session is alive, it's taking a screenshot
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// 1. Presigns a short lived url at which the newly inserted object is
+///     reachable, the html files aren't public.
+///
+/// 2. Takes a screenshot of the object (expecting a html page) and finds links
+///     in the page and their positions.
+///
+/// 3. Stores the screenshot in an S3.
+///
+/// 4. Stores the anchors (<a href>) in an S3.
+pub async fn handle(state: &State, record: NewS3Object) -> Result<(), Error> {
+    // 1.
+    let url = state
+        .s3
+        .presigned_url(&record, state.conf.presigned_url_ttl())
+        .await?;
+
+    // 2.
+    // the url carries a signature, so it's not logged
+    log::trace!("Capturing a screenshot of html file {}", record.key);
+    let (screenshot, anchors) = metrics::time("screenshot", async {
+        state
+            .browser
+            .lock()
+            .await
+            .capture_jpeg_screenshot_and_extract_anchors(&url)
+            .await
+    })
+    .await?;
+    metrics::SCREENSHOT_BYTES.set(screenshot.len() as i64);
+    if screenshot.len() > state.conf.max_screenshot_size {
+        log::warn!(
+            "Screenshot of {} is {} bytes, that's {} bytes too many",
+            record.key,
+            screenshot.len(),
+            screenshot.len() - state.conf.max_screenshot_size
+        );
+    }
+
+    // 3.
+    log::trace!(
+        "Captured screenshot of {} bytes, uploading to S3",
+        screenshot.len()
+    );
+    state
+        .s3
+        .put(
+            state.conf.screenshot_bucket_name.clone(),
+            record.key.clone(),
+            screenshot,
+            shared::s3::PutConf {
+                cache_control: Some("public, immutable".to_string()),
+                content_type: Some("image/jpeg".to_string()),
+                ..Default::default()
+            }
+            .with_trace_id(&record.key),
+        )
+        .await?;
+
+    // 4.
+    if !anchors.is_empty() {
+        log::trace!("Storing {} anchors to S3", anchors.len());
+        state
+            .s3
+            .put(
+                state.conf.anchor_bucket_name.clone(),
+                record.key.clone(),
+                serde_json::to_string(&anchors)?.into(),
+                shared::s3::PutConf {
+                    content_type: Some("application/json".to_string()),
+                    ..Default::default()
+                }
+                .with_trace_id(&record.key),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser::Headless;
+    use async_trait::async_trait;
+    use shared::rusoto_core::Region;
+    use shared::{anchor::Anchor, tests::*};
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn it_captures_screenshot_and_uploads_to_s3() {
+        let screenshot_bucket_name = "png_bucket";
+        let html_bucket = "html_bucket";
+        let object_key = "test_key";
+        let body = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let region = Region::EuWest2;
+
+        let record = NewS3Object {
+            region: region.name().to_string(),
+            bucket: html_bucket.to_string(),
+            key: object_key.to_string(),
+        };
+
+        let s3_stub = S3Stub {
+            bucket: screenshot_bucket_name.to_string(),
+            key: object_key.to_string(),
+            body: body.clone(),
+            conf: shared::s3::PutConf {
+                cache_control: Some("public, immutable".to_string()),
+                content_type: Some("image/jpeg".to_string()),
+                ..Default::default()
+            }
+            .with_trace_id(object_key),
+            ..Default::default()
+        };
+
+        let browser_stub = BrowserStub {
+            url: format!(
+                "https://s3-{}.amazonaws.com/{}/{}",
+                region.name(),
+                html_bucket,
+                object_key
+            ),
+            screenshot: body.clone(),
+        };
+
+        let conf = Conf {
+            max_screenshot_size: 20,
+            screenshot_bucket_name: screenshot_bucket_name.to_string(),
+            region,
+            ..Default::default()
+        };
+
+        let state = State {
+            conf,
+            s3: Box::new(s3_stub),
+            browser: Mutex::new(Box::new(browser_stub)),
+        };
+
+        handle(&state, record).await.unwrap();
+    }
+
+    struct BrowserStub {
+        url: String,
+        screenshot: Vec<u8>,
+    }
+
+    #[async_trait]
+    impl Headless for BrowserStub {
+        async fn capture_jpeg_screenshot_and_extract_anchors(
+            &mut self,
+            url: &str,
+        ) -> Result<(Vec<u8>, Vec<Anchor>), Error> {
+            assert_eq!(url, &self.url);
+            // TODO: test anchors
+            Ok((self.screenshot.clone(), vec![]))
+        }
+
+        async fn check_session(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use {
     crate::{browser, prelude::*},
-    shared::{S3Ext, SqsExt},
+    shared::S3Ext,
+    tokio::sync::Mutex,
 };
 
 pub struct State {
     pub conf: Conf,
-    pub browser: Box<dyn browser::Headless>,
+    /// The browser session can only navigate to one page at a time.
+    pub browser: Mutex<Box<dyn browser::Headless>>,
     pub s3: Box<dyn S3Ext>,
-    pub sqs: Box<dyn SqsExt>,
 }
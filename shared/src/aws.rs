@@ -0,0 +1,33 @@
+//! Lets the services run against stand-ins of AWS, such as MinIO for S3 or
+//! ElasticMQ for SQS, in docker-compose or CI.
+
+use rusoto_core::Region;
+
+/// Sends requests to given endpoint instead of AWS if there's one. The region
+/// name is kept because requests are still signed with it.
+pub fn region(region: Region, endpoint: Option<&str>) -> Region {
+    match endpoint {
+        Some(endpoint) => Region::Custom {
+            name: region.name().to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        },
+        None => region,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_overrides_endpoint() {
+        assert_eq!(region(Region::EuWest1, None), Region::EuWest1);
+        assert_eq!(
+            region(Region::EuWest1, Some("http://localhost:9324/")),
+            Region::Custom {
+                name: "eu-west-1".to_string(),
+                endpoint: "http://localhost:9324".to_string(),
+            }
+        );
+    }
+}
@@ -1,11 +1,15 @@
 pub mod phrases;
 pub mod words;
 
-use crate::vision::Annotation;
+use crate::vision::{self, Annotation};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::{cmp::Ordering, collections::HashMap};
 
+/// Estimates of text which the OCR is less sure about than this are scaled
+/// down proportionally, because the text might not be what the newsletter says.
+const FULL_CONFIDENCE: f64 = 0.9;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Document(Vec<Phrase>);
 
@@ -14,6 +18,9 @@ pub struct Phrase {
     pub text: String,
     pub estimates: HashMap<Source, f64>,
     pub words: Vec<Word>,
+    /// Average OCR confidence of the words in the phrase, if the OCR told us.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -22,23 +29,65 @@ pub struct Word {
     #[serde(skip)]
     pub raw: String,
     pub estimates: HashMap<Source, f64>,
+    /// OCR confidence of the word, if the OCR told us.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, Serialize)]
-#[serde(rename_all = "snake_case")]
+/// Which estimator made an estimate. Estimators other than the built-in ones
+/// are [`Source::Custom`], see the predictor's `estimate` module.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
 pub enum Source {
     Dealc,
     Voucherc,
     OpenAi,
     CommonPhrases,
+    Custom(String),
+}
+
+impl Source {
+    /// Same as the serialized name.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Dealc => "dealc",
+            Self::Voucherc => "voucherc",
+            Self::OpenAi => "open_ai",
+            Self::CommonPhrases => "common_phrases",
+            Self::Custom(name) => name,
+        }
+    }
+}
+
+impl From<String> for Source {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "dealc" => Self::Dealc,
+            "voucherc" => Self::Voucherc,
+            "open_ai" => Self::OpenAi,
+            "common_phrases" => Self::CommonPhrases,
+            _ => Self::Custom(name),
+        }
+    }
+}
+
+impl From<Source> for String {
+    fn from(source: Source) -> Self {
+        match source {
+            Source::Custom(name) => name,
+            source => source.as_str().to_string(),
+        }
+    }
 }
 
 impl Document {
     pub fn from_ocr(annotation: &Annotation) -> Self {
         Self(
-            phrases::from_ocr(annotation)
+            phrases::from_ocr_with_words(annotation)
                 .into_iter()
-                .map(Phrase::new)
+                .map(|(text, ocr_words)| {
+                    Phrase::new(text).with_confidence(&ocr_words)
+                })
                 .collect(),
         )
     }
@@ -84,12 +133,53 @@ impl Phrase {
             text,
             words,
             estimates: HashMap::default(),
+            confidence: None,
         }
     }
 
+    /// Copies the OCR confidence of the words the phrase was built from.
+    fn with_confidence(mut self, ocr_words: &[vision::Word]) -> Self {
+        let confidences: Vec<_> = ocr_words
+            .iter()
+            .filter_map(|w| w.confidence)
+            .map(f64::from)
+            .collect();
+        if !confidences.is_empty() {
+            self.confidence = Some(
+                confidences.iter().sum::<f64>() / confidences.len() as f64,
+            );
+        }
+
+        // the words of the phrase come from the OCR words in order, but some
+        // OCR words are split or filtered out, so a word which doesn't match
+        // must not skip the rest
+        let mut next = 0;
+        for word in &mut self.words {
+            let position = ocr_words[next..]
+                .iter()
+                .position(|w| w.word == word.raw)
+                .map(|offset| next + offset);
+            word.confidence = position
+                .and_then(|position| ocr_words[position].confidence)
+                .map(f64::from);
+            if let Some(position) = position {
+                next = position + 1;
+            }
+        }
+
+        self
+    }
+
+    /// Scaled down if the OCR isn't confident about the phrase.
     pub fn avg_estimate(&self) -> f64 {
         let total: f64 = self.estimates.values().copied().sum();
-        total / self.estimates.len() as f64
+        total / self.estimates.len() as f64 * self.confidence_weight()
+    }
+
+    /// Between 0 and 1, multiply estimates with it to take into account how
+    /// sure the OCR was about the text.
+    pub fn confidence_weight(&self) -> f64 {
+        confidence_weight(self.confidence)
     }
 
     pub fn top_word(&self) -> Option<&Word> {
@@ -116,11 +206,120 @@ impl Word {
             raw,
             text,
             estimates: HashMap::default(),
+            confidence: None,
         }
     }
 
+    /// Scaled down if the OCR isn't confident about the word.
     pub fn avg_estimate(&self) -> f64 {
         let total: f64 = self.estimates.values().copied().sum();
-        total / self.estimates.len() as f64
+        total / self.estimates.len() as f64 * self.confidence_weight()
+    }
+
+    /// See [`Phrase::confidence_weight`].
+    pub fn confidence_weight(&self) -> f64 {
+        confidence_weight(self.confidence)
+    }
+}
+
+fn confidence_weight(confidence: Option<f64>) -> f64 {
+    confidence
+        .map(|c| (c / FULL_CONFIDENCE).clamp(0.0, 1.0))
+        .unwrap_or(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_copies_ocr_confidence_and_scales_estimates_down() {
+        let word = |w: &str, x, confidence| vision::Word {
+            word: w.to_string(),
+            top_left: (x, 0).into(),
+            bottom_right: (x + 50, 10).into(),
+            confidence,
+            ..Default::default()
+        };
+        let annotation = Annotation {
+            text: "Use SUMMER20".to_string(),
+            words: vec![
+                word("Use", 0, Some(0.9)),
+                word("SUMMER20", 60, Some(0.45)),
+            ],
+        };
+
+        let mut document = Document::from_ocr(&annotation);
+
+        let phrase = &mut document.phrases_mut()[0];
+        assert_eq!(phrase.text, "Use SUMMER20");
+        assert_eq!(
+            phrase.confidence,
+            Some((0.9f32 as f64 + 0.45f32 as f64) / 2.0)
+        );
+        assert_eq!(phrase.words[0].confidence, Some(0.9f32 as f64));
+        assert_eq!(phrase.words[1].confidence, Some(0.45f32 as f64));
+
+        phrase.words[0].estimates.insert(Source::Voucherc, 0.8);
+        phrase.words[1].estimates.insert(Source::Voucherc, 0.8);
+        assert!((phrase.words[0].avg_estimate() - 0.8).abs() < 0.001);
+        assert!((phrase.words[1].avg_estimate() - 0.4).abs() < 0.001);
+
+        // the break phrase has no words to take confidence from
+        assert_eq!(document.phrases()[1].confidence, None);
+    }
+
+    #[test]
+    fn it_copies_confidence_after_word_which_does_not_match() {
+        let word = |w: &str, x, confidence| vision::Word {
+            word: w.to_string(),
+            top_left: (x, 0).into(),
+            bottom_right: (x + 50, 10).into(),
+            confidence,
+            ..Default::default()
+        };
+        let annotation = Annotation {
+            text: "Use SUMMER 2020 today".to_string(),
+            words: vec![
+                word("Use", 0, Some(0.9)),
+                // split into two words of the phrase
+                word("SUMMER 2020", 60, Some(0.7)),
+                word("today", 120, Some(0.5)),
+            ],
+        };
+
+        let document = Document::from_ocr(&annotation);
+
+        let phrase = &document.phrases()[0];
+        assert_eq!(
+            phrase
+                .words
+                .iter()
+                .map(|w| w.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Use", "SUMMER", "2020", "today"]
+        );
+        assert_eq!(
+            phrase
+                .words
+                .iter()
+                .map(|w| w.confidence)
+                .collect::<Vec<_>>(),
+            vec![Some(0.9f32 as f64), None, None, Some(0.5f32 as f64)]
+        );
+    }
+
+    #[test]
+    fn it_serializes_source_by_name() {
+        let json =
+            serde_json::json!({ "dealc": 0.5, "open_ai": 0.1, "x": 1.0 });
+
+        let estimates: HashMap<Source, f64> =
+            serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(estimates[&Source::Dealc], 0.5);
+        assert_eq!(estimates[&Source::OpenAi], 0.1);
+        assert_eq!(estimates[&Source::Custom("x".to_string())], 1.0);
+        assert_eq!(serde_json::to_value(&estimates).unwrap(), json);
     }
 }
@@ -28,8 +28,20 @@ struct BoundingBox {
 }
 
 pub fn from_ocr(annotation: &Annotation) -> Vec<String> {
+    from_ocr_with_words(annotation)
+        .into_iter()
+        .map(|(phrase, _)| phrase)
+        .collect()
+}
+
+/// Like [`from_ocr`], but each phrase comes with the OCR words it was built
+/// from. The `<br>` phrases have no words.
+pub fn from_ocr_with_words(
+    annotation: &Annotation,
+) -> Vec<(String, Vec<Word>)> {
     let all_words: Vec<_> = annotation.words.iter().collect();
-    let sentences = sentences_from_words(&annotation.text, &all_words);
+    // the OCR might know better than our heuristics where a block ends
+    let sentences = sentences_from_words(&annotation.text, &all_words, true);
 
     // splits the sentences by <br>, basically version of
     // `sentences.split_mut(Sentence::BreakSentences)`
@@ -88,24 +100,33 @@ pub fn from_ocr(annotation: &Annotation) -> Vec<String> {
             .flatten()
             .collect();
 
-        for s in sentences_from_words(&sentences_text, &words) {
+        // the blocks were merged because they're aligned, therefore we
+        // ignore whether the OCR put them into different blocks
+        for s in sentences_from_words(&sentences_text, &words, false) {
             match s {
                 // since we've already checked that they are aligned as blocks,
                 // we can put them together as sentences
                 Sentence::BreakSentences => (),
-                Sentence::Full { .. } => output.push(s.to_string()),
+                Sentence::Full { ref words } => output.push((
+                    s.to_string(),
+                    words.iter().map(|w| (*w).clone()).collect(),
+                )),
             }
         }
 
-        output.push(Sentence::BreakSentences.to_string());
+        output.push((Sentence::BreakSentences.to_string(), vec![]));
     }
 
     output
 }
 
+/// If `respect_ocr_blocks` is set, two consecutive words which the OCR put
+/// into different blocks are never in the same sentence, provided that the OCR
+/// told us the blocks.
 fn sentences_from_words<'a>(
     text: &str,
     words: &'a [&Word],
+    respect_ocr_blocks: bool,
 ) -> Vec<Sentence<'a>> {
     if words.is_empty() {
         return Vec::new();
@@ -200,11 +221,19 @@ fn sentences_from_words<'a>(
             distant_to_the_left() || distant_to_the_right()
         };
 
+        let in_different_ocr_blocks = || {
+            respect_ocr_blocks
+                && matches!((c.block, n.block), (Some(a), Some(b)) if a != b)
+        };
+
         let has_punctuation = || c.word.ends_with(PUNCTUATION);
         let new_paragraph =
             || !c.word.ends_with(':') && dist_y * 2 > cpline_height * 3;
 
-        if is_vertically_distant() || is_horizontally_distant() {
+        if in_different_ocr_blocks()
+            || is_vertically_distant()
+            || is_horizontally_distant()
+        {
             csentence.take().map(|p| sentences.push(p));
             sentences.push(Sentence::BreakSentences);
         } else if has_punctuation() || new_paragraph() {
@@ -404,18 +433,21 @@ mod tests {
             word: String::new(),
             top_left: (0, 0).into(),
             bottom_right: (100, 100).into(),
+            ..Default::default()
         };
 
         let word2 = Word {
             word: String::new(),
             top_left: (110, 15).into(),
             bottom_right: (210, 100).into(),
+            ..Default::default()
         };
 
         let word3 = Word {
             word: String::new(),
             top_left: (250, 13).into(),
             bottom_right: (280, 105).into(),
+            ..Default::default()
         };
 
         let sentence = Sentence::Full {
@@ -450,18 +482,21 @@ mod tests {
             word: "abcd".to_string(),
             top_left: (0, 0).into(),
             bottom_right: (100, 100).into(),
+            ..Default::default()
         };
 
         let word2 = Word {
             word: String::new(),
             top_left: (110, 0).into(),
             bottom_right: (210, 100).into(),
+            ..Default::default()
         };
 
         let word3 = Word {
             word: String::new(),
             top_left: (250, 0).into(),
             bottom_right: (280, 100).into(),
+            ..Default::default()
         };
 
         let sentence = Sentence::Full {
@@ -475,6 +510,34 @@ mod tests {
         assert_eq!(50, sentence.avg_word_spacing());
     }
 
+    #[test]
+    fn it_splits_words_in_different_ocr_blocks() {
+        let word = |w: &str, x, block| Word {
+            word: w.to_string(),
+            top_left: (x, 0).into(),
+            bottom_right: (x + 50, 10).into(),
+            block,
+            ..Default::default()
+        };
+        let annotation = |block_a, block_b| Annotation {
+            text: "Hello there".to_string(),
+            words: vec![word("Hello", 0, block_a), word("there", 500, block_b)],
+        };
+
+        assert_eq!(
+            from_ocr(&annotation(None, None)),
+            vec!["Hello there", "<br>"]
+        );
+        assert_eq!(
+            from_ocr(&annotation(Some(0), Some(0))),
+            vec!["Hello there", "<br>"]
+        );
+        assert_eq!(
+            from_ocr(&annotation(Some(0), Some(1))),
+            vec!["Hello", "<br>", "there", "<br>"]
+        );
+    }
+
     #[test]
     fn it_parses_text3() {
         let document = testing_document("parse_text3");
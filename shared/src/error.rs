@@ -0,0 +1,391 @@
+//! Error shared by all services. Each error has a [`Category`] which tells the
+//! worker whether to keep polling, and a code which says what exactly went
+//! wrong. Both are logged, and persisted with failed jobs, as
+//! `{category}.{code}`, e.g. `malformed_input.json`. The codes are stable so
+//! that we can search for them.
+//!
+//! 1. Recoverable errors are caused by e.g. a malformed message body or a
+//!    flaky network. When a recoverable error occurs, the worker logs it,
+//!    skips current message and polls for next one. The message is retried
+//!    once its visibility timeout elapses, or after the error's retry hint.
+//! 2. Fatal errors are caused by e.g. lost SQS or browser connection. When a
+//!    fatal error occurs, it is propagated to the main thread which returns it
+//!    and exits. We rely on supervision, such as k8s controller, that restarts
+//!    failed jobs.
+
+use crate::worker::HandlerError;
+use rusoto_core::RusotoError;
+use rusoto_s3::GetObjectError;
+use rusoto_sqs::{
+    ChangeMessageVisibilityError, DeleteMessageBatchError, DeleteMessageError,
+    GetQueueAttributesError, ReceiveMessageError, SendMessageError,
+};
+use std::{
+    any::{Any, TypeId},
+    error::Error as StdError,
+    fmt::{self, Display},
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+/// If an upstream API tells us to slow down, we retry the message after this
+/// long.
+const QUOTA_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// A request timed out or a connection dropped, retrying likely helps.
+    TransientNetwork,
+    /// The message, an object or a response isn't what we expect.
+    MalformedInput,
+    /// An upstream API rate limits us or we've run out of quota.
+    UpstreamQuota,
+    /// We cannot talk to the infrastructure we depend on, such as SQS, the
+    /// database or the browser. The service terminates.
+    FatalInfra,
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TransientNetwork => "transient_network",
+            Self::MalformedInput => "malformed_input",
+            Self::UpstreamQuota => "upstream_quota",
+            Self::FatalInfra => "fatal_infra",
+        }
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, Self::FatalInfra)
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    category: Category,
+    code: &'static str,
+    message: String,
+    source: Option<Arc<dyn StdError + Send + Sync>>,
+    retry_after: Option<Duration>,
+}
+
+impl Error {
+    /// This error gets logged but service continues polling sqs.
+    pub fn new(reason: impl Display) -> Self {
+        Self::with_category(Category::MalformedInput, reason)
+    }
+
+    /// The service terminates.
+    pub fn fatal(reason: impl Display) -> Self {
+        Self::with_category(Category::FatalInfra, reason)
+    }
+
+    pub fn transient(reason: impl Display) -> Self {
+        Self::with_category(Category::TransientNetwork, reason)
+    }
+
+    pub fn quota(reason: impl Display) -> Self {
+        Self::with_category(Category::UpstreamQuota, reason)
+            .with_retry_after(QUOTA_RETRY_AFTER)
+    }
+
+    pub fn with_category(category: Category, reason: impl Display) -> Self {
+        Self {
+            category,
+            code: "other",
+            message: reason.to_string(),
+            source: None,
+            retry_after: None,
+        }
+    }
+
+    /// Must be a short snake_case identifier which doesn't change over time.
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_source(
+        mut self,
+        source: impl StdError + Send + Sync + 'static,
+    ) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// The message shouldn't be retried sooner than this.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    pub fn category(&self) -> Category {
+        self.category
+    }
+
+    /// See the module docs.
+    pub fn code(&self) -> String {
+        format!("{}.{}", self.category, self.code)
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        self.category.is_recoverable()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code(), self.message)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
+    }
+}
+
+impl HandlerError for Error {
+    fn new(reason: impl Display) -> Self {
+        Self::new(reason)
+    }
+
+    fn fatal(reason: impl Display) -> Self {
+        Self::fatal(reason)
+    }
+
+    fn is_recoverable(&self) -> bool {
+        self.is_recoverable()
+    }
+
+    fn code(&self) -> Option<String> {
+        Some(self.code())
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after()
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::new(&e).with_code("json").with_source(e)
+    }
+}
+
+/// Without SQS the service cannot work, hence any error is fatal. Other AWS
+/// errors are categorized by what went wrong.
+impl<E: StdError + Send + Sync + 'static> From<RusotoError<E>> for Error {
+    fn from(e: RusotoError<E>) -> Self {
+        let reason = format!("AWS err: {:?}", e);
+        let status = match &e {
+            RusotoError::Unknown(res) => Some(res.status.as_u16()),
+            _ => None,
+        };
+        let error = match &e {
+            _ if is_sqs_error::<E>() => Self::fatal(reason).with_code("sqs"),
+            RusotoError::Credentials(_) => {
+                Self::fatal(reason).with_code("aws_credentials")
+            }
+            RusotoError::Validation(_) => {
+                Self::new(reason).with_code("aws_validation")
+            }
+            RusotoError::Service(service) if is_no_such_key(service) => {
+                Self::new(reason).with_code("missing_object")
+            }
+            RusotoError::HttpDispatch(_) => {
+                Self::transient(reason).with_code("aws_network")
+            }
+            // S3 asks us to slow down with 503
+            RusotoError::Unknown(_)
+                if matches!(status, Some(429) | Some(503)) =>
+            {
+                Self::quota(reason).with_code("aws_throttling")
+            }
+            RusotoError::Unknown(_) if matches!(status, Some(500..=599)) => {
+                Self::transient(reason).with_code("aws")
+            }
+            _ => Self::fatal(reason).with_code("aws"),
+        };
+
+        error.with_source(e)
+    }
+}
+
+fn is_sqs_error<E: 'static>() -> bool {
+    [
+        TypeId::of::<ReceiveMessageError>(),
+        TypeId::of::<DeleteMessageError>(),
+        TypeId::of::<DeleteMessageBatchError>(),
+        TypeId::of::<SendMessageError>(),
+        TypeId::of::<GetQueueAttributesError>(),
+        TypeId::of::<ChangeMessageVisibilityError>(),
+    ]
+    .contains(&TypeId::of::<E>())
+}
+
+fn is_no_such_key(e: &dyn Any) -> bool {
+    matches!(
+        e.downcast_ref::<GetObjectError>(),
+        Some(GetObjectError::NoSuchKey(_))
+    )
+}
+
+impl From<envy::Error> for Error {
+    fn from(e: envy::Error) -> Self {
+        Self::fatal(&e).with_code("conf").with_source(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        use io::ErrorKind::*;
+
+        let error = match e.kind() {
+            NotFound | InvalidData | InvalidInput | UnexpectedEof => {
+                Self::new(&e)
+            }
+            TimedOut | ConnectionReset | ConnectionAborted
+            | ConnectionRefused | BrokenPipe | Interrupted => {
+                Self::transient(&e)
+            }
+            _ => Self::fatal(&e),
+        };
+
+        error.with_code("io").with_source(e)
+    }
+}
+
+impl From<sqlite::Error> for Error {
+    fn from(e: sqlite::Error) -> Self {
+        Self::fatal(&e).with_code("sqlite").with_source(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        let status = e.status().map(|status| status.as_u16());
+        let error = match status {
+            Some(429) => Self::quota(&e),
+            Some(400..=499) => Self::new(&e),
+            _ => Self::transient(&e),
+        };
+
+        error.with_code("http").with_source(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_code_and_message() {
+        let e = Error::transient("Connection reset").with_code("dealc");
+
+        assert!(e.is_recoverable());
+        assert_eq!(e.code(), "transient_network.dealc");
+        assert_eq!(e.to_string(), "[transient_network.dealc] Connection reset");
+        assert_eq!(Error::fatal("Oops").code(), "fatal_infra.other");
+        assert!(!Error::fatal("Oops").is_recoverable());
+    }
+
+    #[test]
+    fn it_chains_source() {
+        let json_err = serde_json::from_str::<u8>("nope").unwrap_err();
+        let e = Error::from(json_err);
+
+        assert_eq!(e.category(), Category::MalformedInput);
+        assert_eq!(e.code(), "malformed_input.json");
+        assert!(e.source().unwrap().is::<serde_json::Error>());
+    }
+
+    #[test]
+    fn it_categorizes_aws_errors() {
+        use rusoto_core::credential::CredentialsError;
+        use rusoto_core::request::{BufferedHttpResponse, HttpDispatchError};
+
+        let category = |e: RusotoError<GetObjectError>| Error::from(e).code();
+        let unknown = |status: u16| {
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: reqwest::StatusCode::from_u16(status).unwrap(),
+                body: Default::default(),
+                headers: Default::default(),
+            })
+        };
+
+        assert_eq!(
+            category(RusotoError::Validation("Too large".to_string())),
+            "malformed_input.aws_validation"
+        );
+        assert_eq!(
+            category(RusotoError::Service(GetObjectError::NoSuchKey(
+                "key".to_string()
+            ))),
+            "malformed_input.missing_object"
+        );
+        assert_eq!(
+            category(RusotoError::HttpDispatch(HttpDispatchError::new(
+                "Connection reset".to_string()
+            ))),
+            "transient_network.aws_network"
+        );
+        assert_eq!(category(unknown(503)), "upstream_quota.aws_throttling");
+        assert_eq!(category(unknown(429)), "upstream_quota.aws_throttling");
+        assert_eq!(category(unknown(500)), "transient_network.aws");
+        assert_eq!(category(unknown(403)), "fatal_infra.aws");
+        assert_eq!(
+            category(RusotoError::Credentials(CredentialsError::new("Nope"))),
+            "fatal_infra.aws_credentials"
+        );
+        assert_eq!(
+            Error::from(RusotoError::<ReceiveMessageError>::HttpDispatch(
+                HttpDispatchError::new("Connection reset".to_string())
+            ))
+            .code(),
+            "fatal_infra.sqs"
+        );
+        assert!(Error::from(unknown(503)).retry_after().is_some());
+    }
+
+    #[test]
+    fn it_categorizes_io_errors() {
+        let category = |kind| Error::from(io::Error::from(kind)).category();
+
+        assert_eq!(
+            category(io::ErrorKind::InvalidData),
+            Category::MalformedInput
+        );
+        assert_eq!(category(io::ErrorKind::NotFound), Category::MalformedInput);
+        assert_eq!(
+            category(io::ErrorKind::TimedOut),
+            Category::TransientNetwork
+        );
+        assert_eq!(
+            category(io::ErrorKind::PermissionDenied),
+            Category::FatalInfra
+        );
+    }
+
+    #[test]
+    fn it_hints_retry_on_quota() {
+        assert_eq!(
+            Error::quota("Slow down").retry_after(),
+            Some(QUOTA_RETRY_AFTER)
+        );
+        assert_eq!(Error::new("Bad").retry_after(), None);
+    }
+}
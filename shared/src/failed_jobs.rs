@@ -0,0 +1,169 @@
+//! Messages which the worker gave up on are recorded in the `failed_jobs` table
+//! so that an operator can inspect why they failed and requeue them once the
+//! cause is fixed.
+
+use sqlite::{Connection, State};
+
+#[derive(Debug, PartialEq)]
+pub struct FailedJob {
+    pub id: i64,
+    /// The queue from which the message was received. When requeued, the
+    /// message is sent back there.
+    pub queue_url: String,
+    /// Can be missing if the message body couldn't be decoded.
+    pub s3_key: Option<String>,
+    pub message_body: String,
+    pub error: String,
+    /// Can be missing if the service's error has no code.
+    pub error_code: Option<String>,
+    pub receive_count: i64,
+    /// UNIX time in seconds
+    pub created_at: i64,
+}
+
+pub struct FailedJobs {
+    conn: Connection,
+}
+
+impl FailedJobs {
+    pub fn open(database_path: &str) -> Result<Self, sqlite::Error> {
+        Ok(Self {
+            conn: Connection::open(database_path)?,
+        })
+    }
+
+    pub fn insert(
+        &self,
+        queue_url: &str,
+        s3_key: Option<&str>,
+        message_body: &str,
+        error: &str,
+        error_code: Option<&str>,
+        receive_count: i64,
+    ) -> Result<(), sqlite::Error> {
+        let mut statement = self.conn.prepare(
+            "INSERT INTO failed_jobs \
+            (queue_url, s3_key, message_body, error, error_code, receive_count) \
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )?;
+        statement.bind(1, queue_url)?;
+        statement.bind(2, s3_key)?;
+        statement.bind(3, message_body)?;
+        statement.bind(4, error)?;
+        statement.bind(5, error_code)?;
+        statement.bind(6, receive_count)?;
+
+        while !matches!(statement.next()?, State::Done) {
+            //
+        }
+
+        Ok(())
+    }
+
+    /// Returns all failed jobs, oldest first.
+    pub fn list(&self) -> Result<Vec<FailedJob>, sqlite::Error> {
+        let mut statement = self.conn.prepare(format!(
+            "SELECT {} FROM failed_jobs ORDER BY id",
+            COLUMNS
+        ))?;
+
+        let mut jobs = vec![];
+        while let State::Row = statement.next()? {
+            jobs.push(read_job(&statement)?);
+        }
+
+        Ok(jobs)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<FailedJob>, sqlite::Error> {
+        let mut statement = self.conn.prepare(format!(
+            "SELECT {} FROM failed_jobs WHERE id = ?",
+            COLUMNS
+        ))?;
+        statement.bind(1, id)?;
+
+        if let State::Row = statement.next()? {
+            Ok(Some(read_job(&statement)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn delete(&self, id: i64) -> Result<(), sqlite::Error> {
+        let mut statement =
+            self.conn.prepare("DELETE FROM failed_jobs WHERE id = ?")?;
+        statement.bind(1, id)?;
+
+        while !matches!(statement.next()?, State::Done) {
+            //
+        }
+
+        Ok(())
+    }
+}
+
+const COLUMNS: &str = "id, queue_url, s3_key, message_body, error, \
+    error_code, receive_count, created_at";
+
+fn read_job(statement: &sqlite::Statement) -> Result<FailedJob, sqlite::Error> {
+    Ok(FailedJob {
+        id: statement.read(0)?,
+        queue_url: statement.read(1)?,
+        s3_key: statement.read(2)?,
+        message_body: statement.read(3)?,
+        error: statement.read(4)?,
+        error_code: statement.read(5)?,
+        receive_count: statement.read(6)?,
+        created_at: statement.read(7)?,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn it_inserts_lists_and_deletes() {
+        let failed_jobs = open_in_memory();
+
+        failed_jobs
+            .insert("queue1", Some("key1"), "{}", "error1", Some("code1"), 5)
+            .unwrap();
+        failed_jobs
+            .insert("queue2", None, "not json", "error2", None, 6)
+            .unwrap();
+
+        let jobs = failed_jobs.list().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].queue_url, "queue1");
+        assert_eq!(jobs[0].s3_key, Some("key1".to_string()));
+        assert_eq!(jobs[0].message_body, "{}");
+        assert_eq!(jobs[0].error, "error1");
+        assert_eq!(jobs[0].error_code, Some("code1".to_string()));
+        assert_eq!(jobs[0].receive_count, 5);
+        assert_eq!(jobs[1].s3_key, None);
+
+        assert_eq!(failed_jobs.get(jobs[1].id).unwrap().as_ref(), jobs.get(1));
+
+        failed_jobs.delete(jobs[0].id).unwrap();
+        assert_eq!(failed_jobs.get(jobs[0].id).unwrap(), None);
+        assert_eq!(failed_jobs.list().unwrap().len(), 1);
+    }
+
+    const MIGRATION_03: &str =
+        include_str!("../../migrations/000003_create_failed_jobs_table.up.sql");
+
+    const MIGRATION_04: &str = include_str!(
+        "../../migrations/000004_add_error_code_to_failed_jobs.up.sql"
+    );
+
+    pub fn open_in_memory() -> FailedJobs {
+        let conn = Connection::open(":memory:").unwrap();
+        conn.execute(MIGRATION_03)
+            .expect("Cannot run failed jobs migration");
+        conn.execute(MIGRATION_04)
+            .expect("Cannot run error code migration");
+
+        FailedJobs { conn }
+    }
+}
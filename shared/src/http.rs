@@ -1,4 +1,18 @@
+//! JSON over HTTP to the services we call, such as the classifiers and OpenAI.
+//!
+//! [`Retrying`] wraps a client and retries requests which failed on a network
+//! error or timed out, with exponential backoff and full jitter. Each endpoint
+//! has a circuit breaker. After [`Conf::http_breaker_threshold`] failures in a
+//! row, requests to the endpoint fail right away until the cooldown elapses.
+//! Then requests are let through again, and the breaker closes on the first
+//! success or opens again on the first failure.
+
+use crate::error::{Category, Error};
 use async_trait::async_trait;
+use rand::Rng;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::{self, Instant};
 
 #[async_trait]
 pub trait Client {
@@ -6,7 +20,13 @@ pub trait Client {
         &self,
         url: &str,
         body: &serde_json::Value,
-    ) -> Result<Vec<u8>, reqwest::Error>;
+    ) -> Result<Vec<u8>, Error>;
+
+    /// Whether requests to the url are let through. It's false while the
+    /// circuit breaker of the endpoint is open.
+    fn is_available(&self, _url: &str) -> bool {
+        true
+    }
 }
 
 #[async_trait]
@@ -15,10 +35,342 @@ impl Client for reqwest::Client {
         &self,
         url: &str,
         body: &serde_json::Value,
-    ) -> Result<Vec<u8>, reqwest::Error> {
+    ) -> Result<Vec<u8>, Error> {
         let response =
             self.post(url).json(body).send().await?.error_for_status()?;
 
         Ok(response.bytes().await?.to_vec())
     }
 }
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// How many times a request is retried after it failed on a network error
+    /// or timed out.
+    ///
+    /// # Default
+    /// 3
+    pub http_max_retries: Option<u32>,
+    /// The delay before the first retry, it doubles with each retry.
+    ///
+    /// # Default
+    /// 200ms
+    pub http_backoff_base_ms: Option<u64>,
+    /// # Default
+    /// 10s
+    pub http_backoff_max_ms: Option<u64>,
+    /// Applies to endpoints which don't have their own timeout.
+    ///
+    /// # Default
+    /// 30s
+    pub http_timeout_secs: Option<u64>,
+    /// After how many failed requests in a row the circuit breaker opens.
+    ///
+    /// # Default
+    /// 5
+    pub http_breaker_threshold: Option<u32>,
+    /// How long the circuit breaker stays open.
+    ///
+    /// # Default
+    /// 60s
+    pub http_breaker_cooldown_secs: Option<u64>,
+}
+
+impl Conf {
+    fn max_retries(&self) -> u32 {
+        self.http_max_retries.unwrap_or(3)
+    }
+
+    fn backoff_base(&self) -> Duration {
+        Duration::from_millis(self.http_backoff_base_ms.unwrap_or(200))
+    }
+
+    fn backoff_max(&self) -> Duration {
+        Duration::from_millis(self.http_backoff_max_ms.unwrap_or(10_000))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.http_timeout_secs.unwrap_or(30))
+    }
+
+    fn breaker_threshold(&self) -> u32 {
+        self.http_breaker_threshold.unwrap_or(5).max(1)
+    }
+
+    fn breaker_cooldown(&self) -> Duration {
+        Duration::from_secs(self.http_breaker_cooldown_secs.unwrap_or(60))
+    }
+}
+
+pub struct Retrying<C> {
+    client: C,
+    conf: Conf,
+    /// Timeouts by url, overriding [`Conf::http_timeout_secs`].
+    timeouts: HashMap<String, Duration>,
+    /// Circuit breakers by url.
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+#[derive(Default)]
+struct Breaker {
+    /// Failed requests in a row.
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl<C> Retrying<C> {
+    pub fn new(client: C, conf: Conf) -> Self {
+        Self {
+            client,
+            conf,
+            timeouts: HashMap::new(),
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Requests to the url time out after given duration.
+    pub fn with_timeout(
+        mut self,
+        url: impl Into<String>,
+        timeout: Duration,
+    ) -> Self {
+        self.timeouts.insert(url.into(), timeout);
+        self
+    }
+
+    fn record_success(&self, url: &str) {
+        self.breakers.lock().unwrap().remove(url);
+    }
+
+    fn record_failure(&self, url: &str) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(url.to_string()).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.conf.breaker_threshold() {
+            log::warn!(
+                "Circuit breaker for {} opens after {} failures",
+                url,
+                breaker.failures
+            );
+            breaker.open_until =
+                Some(Instant::now() + self.conf.breaker_cooldown());
+        }
+    }
+
+    /// Full jitter, i.e. a random delay up to the exponential backoff.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .conf
+            .backoff_base()
+            .checked_mul(1 << attempt.min(16))
+            .unwrap_or_else(|| self.conf.backoff_max())
+            .min(self.conf.backoff_max());
+        let millis = backoff.as_millis() as u64;
+
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+#[async_trait]
+impl<C: Client + Send + Sync> Client for Retrying<C> {
+    async fn post_json(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<Vec<u8>, Error> {
+        let timeout = self
+            .timeouts
+            .get(url)
+            .copied()
+            .unwrap_or_else(|| self.conf.timeout());
+
+        let mut attempt = 0;
+        loop {
+            if !self.is_available(url) {
+                return Err(Error::transient(format!(
+                    "Circuit breaker for {} is open",
+                    url
+                ))
+                .with_code("circuit_open"));
+            }
+
+            let res = time::timeout(timeout, self.client.post_json(url, body))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::transient(format!(
+                        "Request to {} timed out after {:?}",
+                        url, timeout
+                    ))
+                    .with_code("http_timeout"))
+                });
+
+            let e = match res {
+                Ok(body) => {
+                    self.record_success(url);
+                    return Ok(body);
+                }
+                Err(e) => e,
+            };
+
+            // a bad request is our fault, it says nothing about the endpoint
+            let is_upstream_failure = matches!(
+                e.category(),
+                Category::TransientNetwork | Category::UpstreamQuota
+            );
+            if is_upstream_failure {
+                self.record_failure(url);
+            }
+
+            let should_retry = e.category() == Category::TransientNetwork
+                && attempt < self.conf.max_retries();
+            if !should_retry {
+                return Err(e);
+            }
+
+            let delay = self.backoff(attempt);
+            log::warn!("Retrying request in {:?} after: {}", delay, e);
+            time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn is_available(&self, url: &str) -> bool {
+        self.breakers
+            .lock()
+            .unwrap()
+            .get(url)
+            .and_then(|breaker| breaker.open_until)
+            .map(|open_until| Instant::now() >= open_until)
+            .unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const URL: &str = "http://localhost/dealc";
+
+    #[tokio::test]
+    async fn it_retries_transient_errors() {
+        time::pause();
+        let client = Retrying::new(
+            ClientStub::new(vec![
+                Err(Error::transient("Connection reset")),
+                Err(Error::transient("Connection reset")),
+                Ok(vec![1]),
+            ]),
+            Conf::default(),
+        );
+
+        assert_eq!(client.post_json(URL, &json!([])).await.unwrap(), vec![1]);
+        assert_eq!(client.client.calls.load(Ordering::SeqCst), 3);
+        assert!(client.is_available(URL));
+    }
+
+    #[tokio::test]
+    async fn it_does_not_retry_bad_requests() {
+        let client = Retrying::new(
+            ClientStub::new(vec![Err(Error::new("Bad request")), Ok(vec![1])]),
+            Conf::default(),
+        );
+
+        assert!(client.post_json(URL, &json!([])).await.is_err());
+        assert_eq!(client.client.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_opens_circuit_breaker() {
+        time::pause();
+        let conf = Conf {
+            http_max_retries: Some(0),
+            http_breaker_threshold: Some(2),
+            ..Default::default()
+        };
+        let client = Retrying::new(
+            ClientStub::new(vec![
+                Err(Error::quota("Slow down")),
+                Err(Error::transient("Timeout")),
+                Ok(vec![1]),
+            ]),
+            conf,
+        )
+        .with_timeout("http://localhost/openai", Duration::from_secs(1));
+
+        assert!(client.post_json(URL, &json!([])).await.is_err());
+        assert!(client.is_available(URL));
+        assert!(client.post_json(URL, &json!([])).await.is_err());
+        assert!(!client.is_available(URL));
+        assert!(client.is_available("http://localhost/openai"));
+
+        let e = client.post_json(URL, &json!([])).await.unwrap_err();
+        assert_eq!(e.code(), "transient_network.circuit_open");
+        assert_eq!(client.client.calls.load(Ordering::SeqCst), 2);
+
+        time::advance(Duration::from_secs(60)).await;
+        assert!(client.is_available(URL));
+        assert_eq!(client.post_json(URL, &json!([])).await.unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn it_times_out() {
+        time::pause();
+        let conf = Conf {
+            http_max_retries: Some(0),
+            ..Default::default()
+        };
+        let client = Retrying::new(SlowClientStub, conf)
+            .with_timeout(URL, Duration::from_secs(1));
+
+        let e = client.post_json(URL, &json!([])).await.unwrap_err();
+        assert_eq!(e.code(), "transient_network.http_timeout");
+    }
+
+    struct ClientStub {
+        responses: Mutex<Vec<Result<Vec<u8>, Error>>>,
+        calls: AtomicUsize,
+    }
+
+    impl ClientStub {
+        fn new(mut responses: Vec<Result<Vec<u8>, Error>>) -> Self {
+            responses.reverse();
+            Self {
+                responses: Mutex::new(responses),
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Client for ClientStub {
+        async fn post_json(
+            &self,
+            _url: &str,
+            _body: &serde_json::Value,
+        ) -> Result<Vec<u8>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.responses
+                .lock()
+                .unwrap()
+                .pop()
+                .expect("No more responses")
+        }
+    }
+
+    struct SlowClientStub;
+
+    #[async_trait]
+    impl Client for SlowClientStub {
+        async fn post_json(
+            &self,
+            _url: &str,
+            _body: &serde_json::Value,
+        ) -> Result<Vec<u8>, Error> {
+            time::sleep(Duration::from_secs(60)).await;
+            Ok(vec![])
+        }
+    }
+}
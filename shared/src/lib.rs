@@ -1,9 +1,16 @@
 pub mod anchor;
+pub mod aws;
 pub mod document;
+pub mod error;
+pub mod failed_jobs;
 pub mod http;
+pub mod metrics;
 pub mod s3;
+pub mod server;
 pub mod sqs;
+pub mod telemetry;
 pub mod vision;
+pub mod worker;
 
 pub use rusoto_core;
 pub use rusoto_s3;
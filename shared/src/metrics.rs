@@ -0,0 +1,103 @@
+//! Prometheus metrics of a service, served on `/metrics` by the
+//! [`crate::server`]. The worker counts messages and times the handler, each
+//! service times its own steps with [`time`].
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, Encoder, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use std::future::Future;
+
+lazy_static! {
+    pub static ref MESSAGES_RECEIVED: IntCounter = register_int_counter!(
+        "messages_received_total",
+        "Messages received from the input queue"
+    )
+    .unwrap();
+    pub static ref MESSAGES_SUCCEEDED: IntCounter = register_int_counter!(
+        "messages_succeeded_total",
+        "Messages which were handled and deleted"
+    )
+    .unwrap();
+    pub static ref MESSAGES_FAILED: IntCounterVec = register_int_counter_vec!(
+        "messages_failed_total",
+        "Messages whose handling failed, by error category",
+        &["category"]
+    )
+    .unwrap();
+    pub static ref HANDLER_DURATION: Histogram = register_histogram!(
+        "handler_duration_seconds",
+        "How long the handler took with a message, or with a whole batch"
+    )
+    .unwrap();
+    pub static ref STEP_DURATION: HistogramVec = register_histogram_vec!(
+        "step_duration_seconds",
+        "How long a step of the handler, such as an API call, took",
+        &["step"]
+    )
+    .unwrap();
+    pub static ref ESTIMATE_CACHE_LOOKUPS: IntCounterVec =
+        register_int_counter_vec!(
+            "estimate_cache_lookups_total",
+            "Lookups of cached estimates by source and whether they hit",
+            &["source", "result"]
+        )
+        .unwrap();
+    pub static ref SCREENSHOT_BYTES: IntGauge = register_int_gauge!(
+        "screenshot_bytes",
+        "Size of the last screenshot prtsc took"
+    )
+    .unwrap();
+}
+
+/// Observes how long the future took to complete under given step.
+pub async fn time<F: Future>(step: &str, f: F) -> F::Output {
+    let _timer = STEP_DURATION.with_label_values(&[step]).start_timer();
+    f.await
+}
+
+/// Counts a failed message under the category part of the error code, see
+/// [`crate::error`].
+pub fn record_failure(code: Option<&str>) {
+    let category = code
+        .and_then(|code| code.split('.').next())
+        .unwrap_or("unknown");
+    MESSAGES_FAILED.with_label_values(&[category]).inc();
+}
+
+/// All metrics in the Prometheus text format.
+pub fn encode() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut body = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut body)
+        .expect("Metrics are always encodable");
+
+    (encoder.format_type().to_string(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_encodes_metrics() {
+        record_failure(Some("upstream_quota.http"));
+        record_failure(None);
+        assert_eq!(time("test_step", async { 1 }).await, 1);
+
+        let (content_type, body) = encode();
+        let body = String::from_utf8(body).unwrap();
+
+        assert!(content_type.starts_with("text/plain"));
+        assert!(body
+            .contains("messages_failed_total{category=\"upstream_quota\"} 1"));
+        // other tests count failures too
+        assert!(MESSAGES_FAILED.with_label_values(&["unknown"]).get() >= 1);
+        assert!(
+            body.contains("step_duration_seconds_count{step=\"test_step\"} 1")
+        );
+    }
+}
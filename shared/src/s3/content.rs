@@ -0,0 +1,63 @@
+//! Helpers for object bodies which the [`super::S3Ext`] implementations share.
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rusoto_core::RusotoError;
+use std::io::{self, Read, Write};
+
+/// The content encoding of gzipped objects.
+pub const GZIP: &str = "gzip";
+
+pub fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Stops decompressing once the body is over the limit, so that a small object
+/// can't blow up in memory.
+pub fn gunzip<E>(
+    body: &[u8],
+    max_bytes: usize,
+) -> Result<Vec<u8>, RusotoError<E>> {
+    let mut decoded = vec![];
+    GzDecoder::new(body)
+        .take((max_bytes as u64).saturating_add(1))
+        .read_to_end(&mut decoded)?;
+    check_size(decoded.len(), max_bytes)?;
+
+    Ok(decoded)
+}
+
+/// The same error is returned by all implementations when an object is larger
+/// than the caller accepts.
+pub fn check_size<E>(
+    size: usize,
+    max_bytes: usize,
+) -> Result<(), RusotoError<E>> {
+    if size > max_bytes {
+        Err(RusotoError::Validation(format!(
+            "Object is larger than {} bytes",
+            max_bytes
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_gunzips_up_to_limit() {
+        let body = b"{\"text\":\"hello\"}".to_vec();
+        let gzipped = gzip(&body).unwrap();
+
+        assert_eq!(gunzip::<()>(&gzipped, body.len()).unwrap(), body);
+        assert!(matches!(
+            gunzip::<()>(&gzipped, body.len() - 1),
+            Err(RusotoError::Validation(_))
+        ));
+        assert!(gunzip::<()>(&body, body.len()).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Decodes the SQS messages which tell us about new objects. We accept:
+//!
+//! - S3 event notifications, which can have several records. Their keys are
+//!   URL encoded, e.g. a space is `+`;
+//! - the `s3:TestEvent` which S3 sends when a notification is configured. It
+//!   has no records;
+//! - either of the above wrapped in an SNS notification, for buckets which
+//!   notify a topic the queue is subscribed to;
+//! - S3 events delivered by EventBridge, which describe one object each.
+//!
+//! [https://docs.aws.amazon.com/AmazonS3/latest/userguide/notification-content-structure.html]
+//! [https://docs.aws.amazon.com/AmazonS3/latest/userguide/ev-events.html]
+
+use super::NewS3Object;
+use serde::{de::Error as _, Deserialize};
+
+const TEST_EVENT: &str = "s3:TestEvent";
+
+impl NewS3Object {
+    /// All objects the message body tells us about, in order. Empty for test
+    /// events.
+    pub fn from_message(
+        message_body: &str,
+    ) -> Result<Vec<Self>, serde_json::Error> {
+        match serde_json::from_str::<Notification>(message_body)? {
+            Notification::S3 { records } => records
+                .into_iter()
+                .map(|record| {
+                    Ok(Self {
+                        region: record.aws_region,
+                        key: url_decode(&record.s3.object.key)?,
+                        bucket: record.s3.bucket.name,
+                    })
+                })
+                .collect(),
+            Notification::Test { event } if event == TEST_EVENT => {
+                log::info!("Skipping S3 test event");
+                Ok(vec![])
+            }
+            Notification::Test { event } => Err(serde_json::Error::custom(
+                format!("Unknown S3 event {}", event),
+            )),
+            Notification::Sns { message } => Self::from_message(&message),
+            Notification::EventBridge { region, detail } => Ok(vec![Self {
+                region,
+                key: detail.object.key,
+                bucket: detail.bucket.name,
+            }]),
+        }
+    }
+
+    /// The SQS message body which S3 publishes for this object, with only the
+    /// fields we read.
+    pub fn to_event(&self) -> String {
+        serde_json::json!({
+            "Records": [{
+                "awsRegion": self.region,
+                "s3": {
+                    "bucket": { "name": self.bucket },
+                    "object": { "key": url_encode(&self.key) },
+                },
+            }],
+        })
+        .to_string()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Notification {
+    S3 {
+        #[serde(rename = "Records")]
+        records: Vec<Record>,
+    },
+    Test {
+        #[serde(rename = "Event")]
+        event: String,
+    },
+    Sns {
+        /// Another notification serialized as JSON.
+        #[serde(rename = "Message")]
+        message: String,
+    },
+    EventBridge {
+        region: String,
+        detail: Detail,
+    },
+}
+
+#[derive(Deserialize)]
+struct Record {
+    #[serde(rename = "awsRegion")]
+    aws_region: String,
+    s3: Detail,
+}
+
+#[derive(Deserialize)]
+struct Detail {
+    bucket: Bucket,
+    object: Object,
+}
+
+#[derive(Deserialize)]
+struct Bucket {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    key: String,
+}
+
+/// S3 encodes keys like html forms, spaces as `+` and other reserved
+/// characters as `%XX`.
+fn url_decode(key: &str) -> Result<String, serde_json::Error> {
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut chars = key.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [chars.next(), chars.next()];
+                let decoded = match hex {
+                    [Some(h), Some(l)] => std::str::from_utf8(&[h, l])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                bytes.push(decoded.ok_or_else(|| {
+                    serde_json::Error::custom(format!(
+                        "Invalid percent encoding in key {}",
+                        key
+                    ))
+                })?);
+            }
+            b => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(serde_json::Error::custom)
+}
+
+/// Inverse of [`url_decode`].
+fn url_encode(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b' ' => encoded.push('+'),
+            b if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(key: &str) -> NewS3Object {
+        NewS3Object {
+            region: "eu-west-1".to_string(),
+            bucket: "bucket".to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_decodes_all_records_and_their_keys() {
+        let body = json!({
+            "Records": [
+                {
+                    "eventSource": "aws:s3",
+                    "awsRegion": "eu-west-1",
+                    "s3": {
+                        "bucket": { "name": "bucket" },
+                        "object": { "key": "my+newsletter%2B%C3%A9.eml" },
+                    },
+                },
+                {
+                    "awsRegion": "eu-west-1",
+                    "s3": {
+                        "bucket": { "name": "bucket" },
+                        "object": { "key": "second" },
+                    },
+                },
+            ]
+        });
+
+        assert_eq!(
+            NewS3Object::from_message(&body.to_string()).unwrap(),
+            vec![object("my newsletter+é.eml"), object("second")]
+        );
+    }
+
+    #[test]
+    fn it_skips_test_events() {
+        let body = json!({
+            "Service": "Amazon S3",
+            "Event": "s3:TestEvent",
+            "Time": "2021-05-01T10:00:00.000Z",
+            "Bucket": "bucket",
+        });
+
+        assert_eq!(
+            NewS3Object::from_message(&body.to_string()).unwrap(),
+            vec![]
+        );
+
+        let body = json!({ "Event": "s3:SomethingElse" });
+        assert!(NewS3Object::from_message(&body.to_string()).is_err());
+    }
+
+    #[test]
+    fn it_unwraps_sns_notifications() {
+        let body = json!({
+            "Type": "Notification",
+            "TopicArn": "arn:aws:sns:eu-west-1:123:topic",
+            "Message": object("a b").to_event(),
+        });
+
+        assert_eq!(
+            NewS3Object::from_message(&body.to_string()).unwrap(),
+            vec![object("a b")]
+        );
+    }
+
+    #[test]
+    fn it_decodes_eventbridge_events() {
+        let body = json!({
+            "version": "0",
+            "detail-type": "Object Created",
+            "source": "aws.s3",
+            "region": "eu-west-1",
+            "detail": {
+                "bucket": { "name": "bucket" },
+                "object": { "key": "key", "size": 5 },
+            },
+        });
+
+        assert_eq!(
+            NewS3Object::from_message(&body.to_string()).unwrap(),
+            vec![object("key")]
+        );
+    }
+
+    #[test]
+    fn it_round_trips_keys() {
+        for key in &["plain", "a b+c", "dir/100%.html", "ünï"] {
+            assert_eq!(url_decode(&url_encode(key)).unwrap(), *key);
+        }
+        assert!(url_decode("%zz").is_err());
+        assert!(url_decode("%2").is_err());
+    }
+}
@@ -0,0 +1,398 @@
+//! Stores objects as files, which lets us run the services locally without
+//! AWS. Object with key _K_ in bucket _B_ is the file `{root}/B/K`.
+//!
+//! The content type, storage class and metadata of the object are stored as
+//! JSON in the file `{root}/.meta/B/K.json`, outside of the bucket directory so
+//! that they're not listed as keys.
+//!
+//! Keys which would resolve to a file outside of the bucket directory, such as
+//! absolute keys or keys with `..`, are rejected with a validation error.
+
+use super::{content, NewS3Object, ObjectHead, PutConf, S3Ext};
+use async_trait::async_trait;
+use rusoto_core::{request::HttpDispatchError, RusotoError};
+use rusoto_s3::{
+    DeleteObjectError, GetObjectError, HeadObjectError, ListObjectsV2Error,
+    PutObjectError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+
+/// Bucket names cannot start with a dot, so this directory never clashes with
+/// a bucket.
+const META_DIR: &str = ".meta";
+
+pub struct FsS3 {
+    pub root: PathBuf,
+}
+
+/// What S3 stores along with the object body.
+#[derive(Serialize, Deserialize, Default)]
+struct Meta {
+    content_type: Option<String>,
+    storage_class: Option<String>,
+    metadata: HashMap<String, String>,
+}
+
+impl FsS3 {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Errors if the bucket isn't a single directory name or if the key
+    /// isn't a relative path without `..`.
+    pub fn path<E>(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<PathBuf, RusotoError<E>> {
+        let bucket_dir = self.bucket_dir(bucket)?;
+        if !is_relative_without_parent(key) {
+            return Err(RusotoError::Validation(format!(
+                "Key '{}' is not a relative path within the bucket",
+                key
+            )));
+        }
+
+        Ok(bucket_dir.join(key))
+    }
+
+    /// Where the [`Meta`] of the object is stored.
+    fn meta_path<E>(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<PathBuf, RusotoError<E>> {
+        self.path::<E>(bucket, key)?;
+
+        Ok(self
+            .root
+            .join(META_DIR)
+            .join(bucket)
+            .join(format!("{}.json", key)))
+    }
+
+    fn bucket_dir<E>(&self, bucket: &str) -> Result<PathBuf, RusotoError<E>> {
+        if !is_relative_without_parent(bucket)
+            || Path::new(bucket).components().count() != 1
+            || bucket.starts_with('.')
+        {
+            return Err(RusotoError::Validation(format!(
+                "Bucket '{}' is not a directory name",
+                bucket
+            )));
+        }
+
+        Ok(self.root.join(bucket))
+    }
+}
+
+/// Only plain names are allowed, which also rules out absolute paths and
+/// windows prefixes.
+fn is_relative_without_parent(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[async_trait]
+impl S3Ext for FsS3 {
+    /// The acl and cache control of the put conf are ignored.
+    async fn put(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+        conf: PutConf,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let meta = Meta {
+            content_type: conf.content_type,
+            storage_class: conf.storage_class,
+            metadata: conf.metadata,
+        };
+        let meta = serde_json::to_vec(&meta)
+            .map_err(|e| RusotoError::ParseError(e.to_string()))?;
+
+        write(self.meta_path(&bucket, &key)?, meta).await?;
+        write(self.path(&bucket, &key)?, body).await
+    }
+
+    async fn get(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        match fs::read(self.path(&bucket, &key)?).await {
+            Ok(body) => Ok(Some(body)),
+            // the same error as S3 returns
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(
+                RusotoError::Service(GetObjectError::NoSuchKey(e.to_string())),
+            ),
+            Err(e) => Err(dispatch_error(e)),
+        }
+    }
+
+    /// Checks the file size before reading it.
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        max_bytes: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        if let Ok(metadata) = fs::metadata(self.path(&bucket, &key)?).await {
+            content::check_size(metadata.len() as usize, max_bytes)?;
+        }
+
+        self.get(bucket, key).await
+    }
+
+    /// Files which weren't put by us have no metadata.
+    async fn head(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>> {
+        let size = match fs::metadata(self.path(&bucket, &key)?).await {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(dispatch_error(e)),
+        };
+        let meta = match fs::read(self.meta_path(&bucket, &key)?).await {
+            Ok(meta) => serde_json::from_slice(&meta)
+                .map_err(|e| RusotoError::ParseError(e.to_string()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Meta::default(),
+            Err(e) => return Err(dispatch_error(e)),
+        };
+
+        Ok(Some(ObjectHead {
+            size,
+            content_type: meta.content_type,
+            storage_class: meta.storage_class,
+            metadata: meta.metadata,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>> {
+        remove(self.path(&bucket, &key)?).await?;
+        remove(self.meta_path(&bucket, &key)?).await
+    }
+
+    /// Walks the whole bucket directory, keys with slashes are nested files.
+    async fn list_prefix(
+        &self,
+        bucket: String,
+        prefix: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>> {
+        let bucket_dir = self.bucket_dir(&bucket)?;
+        let mut keys = vec![];
+        let mut dirs = vec![bucket_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(dispatch_error(e)),
+            };
+            while let Some(entry) =
+                entries.next_entry().await.map_err(dispatch_error)?
+            {
+                let path = entry.path();
+                if entry.file_type().await.map_err(dispatch_error)?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&bucket_dir)
+                    .expect("Entry is in the bucket")
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(&prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    /// The url is only informative, it's not checked whether the object key
+    /// is valid.
+    fn object_url(&self, object: &NewS3Object) -> String {
+        format!(
+            "file://{}",
+            self.root.join(&object.bucket).join(&object.key).display()
+        )
+    }
+
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        _: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>> {
+        let path = self.path(&object.bucket, &object.key)?;
+
+        Ok(format!("file://{}", path.display()))
+    }
+}
+
+/// Creates the parent directories if they don't exist.
+async fn write<E>(
+    path: PathBuf,
+    content: Vec<u8>,
+) -> Result<(), RusotoError<E>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await.map_err(dispatch_error)?;
+    }
+    fs::write(path, content).await.map_err(dispatch_error)
+}
+
+/// Like S3, it's not an error if there's nothing to remove.
+async fn remove<E>(path: PathBuf) -> Result<(), RusotoError<E>> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(dispatch_error(e)),
+        _ => Ok(()),
+    }
+}
+
+fn dispatch_error<E>(e: io::Error) -> RusotoError<E> {
+    RusotoError::HttpDispatch(HttpDispatchError::new(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[tokio::test]
+    async fn it_puts_and_gets_files() {
+        let root = env::temp_dir().join(format!("fs_s3_{}", process::id()));
+        let s3 = FsS3::new(&root);
+        let conf = PutConf {
+            content_type: Some("application/json".to_string()),
+            storage_class: Some("STANDARD_IA".to_string()),
+            ..Default::default()
+        }
+        .with_metadata("trace-id", "abc");
+
+        s3.put(
+            "bucket".to_string(),
+            "dir/key".to_string(),
+            vec![1, 2, 3],
+            conf.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            s3.get("bucket".to_string(), "dir/key".to_string())
+                .await
+                .unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert!(matches!(
+            s3.get("bucket".to_string(), "missing".to_string()).await,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_)))
+        ));
+
+        let object = NewS3Object {
+            region: "eu-west-1".to_string(),
+            bucket: "bucket".to_string(),
+            key: "dir/key".to_string(),
+        };
+        assert_eq!(
+            s3.object_url(&object),
+            format!("file://{}/bucket/dir/key", root.display())
+        );
+
+        assert_eq!(
+            s3.list_prefix("bucket".to_string(), "dir/".to_string())
+                .await
+                .unwrap(),
+            vec!["dir/key".to_string()]
+        );
+        assert_eq!(
+            s3.head("bucket".to_string(), "dir/key".to_string())
+                .await
+                .unwrap(),
+            Some(ObjectHead {
+                size: 3,
+                content_type: conf.content_type,
+                storage_class: conf.storage_class,
+                metadata: conf.metadata,
+            })
+        );
+        assert!(s3
+            .get_at_most("bucket".to_string(), "dir/key".to_string(), 2)
+            .await
+            .is_err());
+        s3.delete("bucket".to_string(), "dir/key".to_string())
+            .await
+            .unwrap();
+        assert!(s3
+            .list_prefix("bucket".to_string(), "".to_string())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(!root.join(META_DIR).join("bucket/dir/key.json").exists());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_rejects_keys_outside_of_bucket() {
+        let root =
+            env::temp_dir().join(format!("fs_s3_escape_{}", process::id()));
+        let s3 = FsS3::new(&root);
+
+        for key in &["../escape", "dir/../../escape", "/tmp/escape", ""] {
+            assert!(
+                matches!(
+                    s3.put(
+                        "bucket".to_string(),
+                        key.to_string(),
+                        vec![1],
+                        PutConf::default(),
+                    )
+                    .await,
+                    Err(RusotoError::Validation(_))
+                ),
+                "{}",
+                key
+            );
+            assert!(matches!(
+                s3.get("bucket".to_string(), key.to_string()).await,
+                Err(RusotoError::Validation(_))
+            ));
+            assert!(matches!(
+                s3.delete("bucket".to_string(), key.to_string()).await,
+                Err(RusotoError::Validation(_))
+            ));
+        }
+        for bucket in &["..", "a/b", "/tmp", META_DIR] {
+            assert!(matches!(
+                s3.list_prefix(bucket.to_string(), "".to_string()).await,
+                Err(RusotoError::Validation(_))
+            ));
+            assert!(matches!(
+                s3.head(bucket.to_string(), "key".to_string()).await,
+                Err(RusotoError::Validation(_))
+            ));
+        }
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,232 @@
+//! Keeps objects in a map for as long as the process lives. Unlike the
+//! `S3Stub` from the test utils, it accepts any number of puts and gets, which
+//! makes it useful to test several services together. Each object keeps the
+//! [`PutConf`] it was stored with, so that tests can assert on it.
+
+use super::{content, NewS3Object, ObjectHead, PutConf, S3Ext};
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    DeleteObjectError, GetObjectError, HeadObjectError, ListObjectsV2Error,
+    PutObjectError,
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub body: Vec<u8>,
+    pub conf: PutConf,
+}
+
+#[derive(Default)]
+pub struct MemoryS3 {
+    /// Objects by bucket and key.
+    objects: Mutex<HashMap<(String, String), Object>>,
+}
+
+impl MemoryS3 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Object> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(&(bucket.to_string(), key.to_string()))
+            .cloned()
+    }
+
+    /// Returns keys of all objects in the bucket, sorted.
+    pub fn keys(&self, bucket: &str) -> Vec<String> {
+        let mut keys: Vec<_> = self
+            .objects
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(b, _)| b == bucket)
+            .map(|(_, key)| key.clone())
+            .collect();
+        keys.sort();
+
+        keys
+    }
+}
+
+#[async_trait]
+impl S3Ext for MemoryS3 {
+    async fn put(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+        conf: PutConf,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert((bucket, key), Object { body, conf });
+
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        match self.object(&bucket, &key) {
+            Some(object) => Ok(Some(object.body)),
+            // the same error as S3 returns
+            None => Err(RusotoError::Service(GetObjectError::NoSuchKey(
+                format!("{}/{}", bucket, key),
+            ))),
+        }
+    }
+
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        max_bytes: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        let body = self.get(bucket, key).await?;
+        if let Some(body) = &body {
+            content::check_size(body.len(), max_bytes)?;
+        }
+
+        Ok(body)
+    }
+
+    async fn head(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>> {
+        Ok(self.object(&bucket, &key).map(|object| ObjectHead {
+            size: object.body.len() as u64,
+            content_type: object.conf.content_type,
+            storage_class: object.conf.storage_class,
+            metadata: object.conf.metadata,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>> {
+        self.objects.lock().unwrap().remove(&(bucket, key));
+
+        Ok(())
+    }
+
+    async fn list_prefix(
+        &self,
+        bucket: String,
+        prefix: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>> {
+        Ok(self
+            .keys(&bucket)
+            .into_iter()
+            .filter(|key| key.starts_with(&prefix))
+            .collect())
+    }
+
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        _: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>> {
+        Ok(self.object_url(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_puts_and_gets_objects() {
+        let s3 = MemoryS3::new();
+        let conf = PutConf {
+            content_type: Some("application/json".to_string()),
+            ..Default::default()
+        };
+
+        s3.put("bucket".to_string(), "b".to_string(), vec![2], conf.clone())
+            .await
+            .unwrap();
+        s3.put("bucket".to_string(), "a".to_string(), vec![1], conf.clone())
+            .await
+            .unwrap();
+        s3.put("other".to_string(), "c".to_string(), vec![3], conf.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            s3.get("bucket".to_string(), "a".to_string()).await.unwrap(),
+            Some(vec![1])
+        );
+        assert!(matches!(
+            s3.get("bucket".to_string(), "c".to_string()).await,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_)))
+        ));
+        assert_eq!(
+            s3.object("other", "c"),
+            Some(Object {
+                body: vec![3],
+                conf
+            })
+        );
+        assert_eq!(s3.keys("bucket"), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn it_heads_lists_and_deletes_objects() {
+        let s3 = MemoryS3::new();
+        let conf = PutConf::default().with_metadata("source", "test");
+        for key in &["dir/a", "dir/b", "other"] {
+            s3.put(
+                "bucket".to_string(),
+                key.to_string(),
+                vec![1, 2],
+                conf.clone(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            s3.list_prefix("bucket".to_string(), "dir/".to_string())
+                .await
+                .unwrap(),
+            vec!["dir/a".to_string(), "dir/b".to_string()]
+        );
+        let head = s3
+            .head("bucket".to_string(), "dir/a".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.size, 2);
+        assert_eq!(head.metadata["source"], "test");
+        assert!(matches!(
+            s3.get_at_most("bucket".to_string(), "dir/a".to_string(), 1)
+                .await,
+            Err(RusotoError::Validation(_))
+        ));
+
+        s3.delete("bucket".to_string(), "dir/a".to_string())
+            .await
+            .unwrap();
+        s3.delete("bucket".to_string(), "dir/a".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            s3.head("bucket".to_string(), "dir/a".to_string())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,455 @@
+mod content;
+mod event;
+pub mod fs;
+pub mod memory;
+pub mod notify;
+mod url;
+
+pub use url::UrlBuilder;
+
+use crate::{aws, telemetry};
+use async_trait::async_trait;
+use futures::StreamExt;
+use rusoto_core::credential::{ChainProvider, ProvideAwsCredentials};
+use rusoto_core::Region;
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    util::{PreSignedRequest, PreSignedRequestOption},
+    DeleteObjectError, DeleteObjectRequest, GetObjectError, GetObjectRequest,
+    HeadObjectError, HeadObjectRequest, ListObjectsV2Error,
+    ListObjectsV2Request, PutObjectError, PutObjectRequest, S3Client, S3,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap, convert::TryFrom, path::PathBuf, str::FromStr,
+    time::Duration,
+};
+
+/// Implements only methods which this project requires instead of all
+/// [`rusoto_s3::S3`] methods, which makes it more comfortable to write stubs
+/// and test it.
+#[async_trait]
+pub trait S3Ext {
+    async fn put(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+        conf: PutConf,
+    ) -> Result<(), RusotoError<PutObjectError>>;
+
+    async fn get(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>>;
+
+    /// Like [`S3Ext::get`], but stops reading the body and errors with
+    /// [`RusotoError::Validation`] once it's over given number of bytes.
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        max_bytes: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>>;
+
+    /// Returns none if there's no such object.
+    async fn head(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>>;
+
+    /// Deleting an object which doesn't exist is not an error.
+    async fn delete(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>>;
+
+    /// Keys of all objects in the bucket which start with given prefix, sorted.
+    async fn list_prefix(
+        &self,
+        bucket: String,
+        prefix: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>>;
+
+    /// Where can the object be downloaded from, provided it's public.
+    fn object_url(&self, object: &NewS3Object) -> String {
+        UrlBuilder::default().object_url(object)
+    }
+
+    /// A url at which the object can be downloaded for given time even though
+    /// it's private. Backends without access control return
+    /// [`S3Ext::object_url`].
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        expires_in: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>>;
+}
+
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct PutConf {
+    pub acl: Option<String>,
+    pub cache_control: Option<String>,
+    pub content_type: Option<String>,
+    /// Stored as user defined metadata along with the object.
+    pub metadata: HashMap<String, String>,
+    /// # Default
+    /// See [`Conf::s3_storage_class`].
+    pub storage_class: Option<String>,
+}
+
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct ObjectHead {
+    /// Of the body as stored, i.e. compressed if it's gzipped.
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub storage_class: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl PutConf {
+    /// Records the trace id derived from the object's key, see
+    /// [`crate::telemetry`].
+    pub fn with_trace_id(mut self, key: &str) -> Self {
+        self.metadata.insert(
+            telemetry::TRACE_ID_METADATA_KEY.to_string(),
+            telemetry::trace_id(key),
+        );
+        self
+    }
+
+    pub fn with_metadata(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// Where the objects are stored. Either `s3`, `fs:///path/to/dir` or
+    /// `mem://`. The latter two let us run the services without AWS.
+    ///
+    /// # Default
+    /// S3
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    /// Sends S3 requests to this url instead of AWS, e.g.
+    /// `http://localhost:9000` for MinIO.
+    pub s3_endpoint: Option<String>,
+    /// Whether object urls name the bucket in the path rather than in the
+    /// host. Stand-ins such as MinIO support only the former out of the box.
+    ///
+    /// # Default
+    /// true
+    pub s3_path_style: Option<bool>,
+    /// Storage class of objects whose [`PutConf`] doesn't set one, e.g.
+    /// `STANDARD_IA`.
+    ///
+    /// # Default
+    /// If not set, S3 stores objects as `STANDARD`.
+    pub s3_storage_class: Option<String>,
+    /// If enabled, JSON objects such as the OCR output, anchors and predictions
+    /// are gzipped with `Content-Encoding: gzip`. Gzipped objects are
+    /// decompressed on get, whether this is enabled or not.
+    #[serde(default)]
+    pub s3_gzip_json: bool,
+}
+
+impl Conf {
+    pub fn s3_path_style(&self) -> bool {
+        self.s3_path_style.unwrap_or(true)
+    }
+
+    pub fn client(&self, region: Region) -> Box<dyn S3Ext> {
+        match &self.storage_backend {
+            StorageBackend::S3 => {
+                let region = aws::region(region, self.s3_endpoint.as_deref());
+                Box::new(AwsS3 {
+                    client: S3Client::new(region.clone()),
+                    region,
+                    credentials: ChainProvider::new(),
+                    urls: UrlBuilder::new(
+                        self.s3_endpoint.clone(),
+                        self.s3_path_style(),
+                    ),
+                    storage_class: self.s3_storage_class.clone(),
+                    gzip_json: self.s3_gzip_json,
+                })
+            }
+            StorageBackend::Fs(root) => Box::new(fs::FsS3::new(root)),
+            StorageBackend::Memory => Box::new(memory::MemoryS3::new()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum StorageBackend {
+    S3,
+    /// See [`fs::FsS3`].
+    Fs(PathBuf),
+    /// See [`memory::MemoryS3`]. Objects are lost when the process exits.
+    Memory,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        Self::S3
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s3" => Ok(Self::S3),
+            "mem://" => Ok(Self::Memory),
+            _ => match s.strip_prefix("fs://") {
+                Some(root) if !root.is_empty() => Ok(Self::Fs(root.into())),
+                _ => Err(format!("Unknown storage backend '{}'", s)),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for StorageBackend {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// S3 or a stand-in which speaks its API.
+pub struct AwsS3 {
+    client: S3Client,
+    region: Region,
+    /// The same chain the client reads its credentials from, used to sign
+    /// presigned urls.
+    credentials: ChainProvider,
+    urls: UrlBuilder,
+    /// See [`Conf::s3_storage_class`].
+    storage_class: Option<String>,
+    /// See [`Conf::s3_gzip_json`].
+    gzip_json: bool,
+}
+
+#[async_trait]
+impl S3Ext for AwsS3 {
+    async fn put(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+        conf: PutConf,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let gzip = self.gzip_json
+            && conf.content_type.as_deref() == Some("application/json");
+        let body = if gzip { content::gzip(&body)? } else { body };
+
+        let req = PutObjectRequest {
+            acl: conf.acl,
+            cache_control: conf.cache_control,
+            content_type: conf.content_type,
+            content_encoding: Some(content::GZIP.to_string()).filter(|_| gzip),
+            metadata: Some(conf.metadata).filter(|m| !m.is_empty()),
+            body: Some(body.into()),
+            bucket,
+            key,
+            storage_class: conf
+                .storage_class
+                .or_else(|| self.storage_class.clone()),
+            ..Default::default()
+        };
+        self.client.put_object(req).await?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        self.get_at_most(bucket, key, usize::MAX).await
+    }
+
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        max_bytes: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        let output = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket,
+                key,
+                ..Default::default()
+            })
+            .await?;
+        if let Some(len) = output.content_length {
+            content::check_size(len.max(0) as usize, max_bytes)?;
+        }
+
+        let mut stream = match output.body {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        let mut body = vec![];
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            content::check_size(body.len() + chunk.len(), max_bytes)?;
+            body.extend_from_slice(&chunk);
+        }
+
+        if output.content_encoding.as_deref() == Some(content::GZIP) {
+            body = content::gunzip(&body, max_bytes)?;
+        }
+
+        Ok(Some(body))
+    }
+
+    async fn head(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>> {
+        let req = HeadObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        };
+        match self.client.head_object(req).await {
+            Ok(output) => Ok(Some(ObjectHead {
+                size: output.content_length.unwrap_or_default().max(0) as u64,
+                content_type: output.content_type,
+                storage_class: output.storage_class,
+                metadata: output.metadata.unwrap_or_default(),
+            })),
+            // responses to head requests have no body to tell the error from
+            Err(RusotoError::Unknown(res)) if res.status.as_u16() == 404 => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>> {
+        let req = DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        };
+        self.client.delete_object(req).await?;
+        Ok(())
+    }
+
+    async fn list_prefix(
+        &self,
+        bucket: String,
+        prefix: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>> {
+        let mut keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let req = ListObjectsV2Request {
+                bucket: bucket.clone(),
+                prefix: Some(prefix.clone()),
+                continuation_token,
+                ..Default::default()
+            };
+            let output = self.client.list_objects_v2(req).await?;
+            keys.extend(
+                output
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key),
+            );
+
+            continuation_token = output.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        // S3 lists keys in UTF-8 binary order already, stand-ins might not
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn object_url(&self, object: &NewS3Object) -> String {
+        self.urls.object_url(object)
+    }
+
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        expires_in: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>> {
+        let credentials = self.credentials.credentials().await?;
+        let req = GetObjectRequest {
+            bucket: object.bucket.clone(),
+            key: object.key.clone(),
+            ..Default::default()
+        };
+
+        Ok(req.get_presigned_url(
+            &self.region,
+            &credentials,
+            &PreSignedRequestOption { expires_in },
+        ))
+    }
+}
+
+/// An object whose creation we were notified about, see
+/// [`NewS3Object::from_message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewS3Object {
+    pub region: String,
+    pub key: String,
+    pub bucket: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_storage_backend() {
+        assert_eq!("s3".parse::<StorageBackend>(), Ok(StorageBackend::S3));
+        assert_eq!(
+            "mem://".parse::<StorageBackend>(),
+            Ok(StorageBackend::Memory)
+        );
+        assert_eq!(
+            "fs:///tmp/newsletter".parse::<StorageBackend>(),
+            Ok(StorageBackend::Fs("/tmp/newsletter".into()))
+        );
+        assert!("fs://".parse::<StorageBackend>().is_err());
+        assert!("gcs://bucket".parse::<StorageBackend>().is_err());
+    }
+
+    #[test]
+    fn it_records_trace_id_in_metadata() {
+        let conf = PutConf::default().with_trace_id("key");
+
+        assert_eq!(
+            conf.metadata.get(telemetry::TRACE_ID_METADATA_KEY),
+            Some(&telemetry::trace_id("key"))
+        );
+    }
+}
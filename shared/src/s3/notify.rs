@@ -0,0 +1,189 @@
+//! Mirrors the S3 to SQS notifications which chain the services in production.
+//! Wraps another [`S3Ext`] and after each put into a bucket with a configured
+//! queue, it sends the same message to the queue as S3 would.
+
+use super::{NewS3Object, ObjectHead, PutConf, S3Ext};
+use crate::SqsExt;
+use async_trait::async_trait;
+use rusoto_core::{request::HttpDispatchError, RusotoError};
+use rusoto_s3::{
+    DeleteObjectError, GetObjectError, HeadObjectError, ListObjectsV2Error,
+    PutObjectError,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+pub struct NotifyingS3<S, Q> {
+    s3: S,
+    sqs: Arc<Q>,
+    region: String,
+    /// Queue urls by bucket name.
+    queues: HashMap<String, String>,
+}
+
+impl<S, Q> NotifyingS3<S, Q> {
+    pub fn new(s3: S, sqs: Arc<Q>, region: impl Into<String>) -> Self {
+        Self {
+            s3,
+            sqs,
+            region: region.into(),
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Puts into the bucket will send a message to the queue.
+    pub fn notify(
+        mut self,
+        bucket: impl Into<String>,
+        queue_url: impl Into<String>,
+    ) -> Self {
+        self.queues.insert(bucket.into(), queue_url.into());
+        self
+    }
+}
+
+#[async_trait]
+impl<S, Q> S3Ext for NotifyingS3<S, Q>
+where
+    S: S3Ext + Send + Sync,
+    Q: SqsExt + Send + Sync,
+{
+    async fn put(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+        conf: PutConf,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let queue_url = self.queues.get(&bucket).cloned();
+        let object = NewS3Object {
+            region: self.region.clone(),
+            bucket: bucket.clone(),
+            key: key.clone(),
+        };
+        self.s3.put(bucket, key, body, conf).await?;
+
+        if let Some(queue_url) = queue_url {
+            log::trace!("Notifying {} about {:?}", queue_url, object);
+            self.sqs
+                .send(queue_url, object.to_event())
+                .await
+                .map_err(|e| {
+                    RusotoError::HttpDispatch(HttpDispatchError::new(format!(
+                        "Cannot notify about new object: {}",
+                        e
+                    )))
+                })?;
+        }
+
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        self.s3.get(bucket, key).await
+    }
+
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        max_bytes: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        self.s3.get_at_most(bucket, key, max_bytes).await
+    }
+
+    async fn head(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>> {
+        self.s3.head(bucket, key).await
+    }
+
+    /// S3 doesn't notify the services about deleted objects.
+    async fn delete(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>> {
+        self.s3.delete(bucket, key).await
+    }
+
+    async fn list_prefix(
+        &self,
+        bucket: String,
+        prefix: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>> {
+        self.s3.list_prefix(bucket, prefix).await
+    }
+
+    fn object_url(&self, object: &NewS3Object) -> String {
+        self.s3.object_url(object)
+    }
+
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        expires_in: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>> {
+        self.s3.presigned_url(object, expires_in).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{s3::memory::MemoryS3, sqs::memory::MemorySqs};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn it_sends_message_on_put() {
+        let sqs = Arc::new(MemorySqs::new(
+            Duration::from_secs(30),
+            Duration::from_millis(0),
+        ));
+        let s3 = NotifyingS3::new(MemoryS3::new(), Arc::clone(&sqs), "local")
+            .notify("screenshots", "ocr_queue");
+
+        s3.put(
+            "screenshots".to_string(),
+            "key".to_string(),
+            vec![1],
+            PutConf::default(),
+        )
+        .await
+        .unwrap();
+        s3.put(
+            "anchors".to_string(),
+            "key".to_string(),
+            vec![2],
+            PutConf::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            s3.get("anchors".to_string(), "key".to_string())
+                .await
+                .unwrap(),
+            Some(vec![2])
+        );
+        let messages = sqs
+            .receive_batch("ocr_queue".to_string(), 10)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            NewS3Object::from_message(messages[0].body.as_ref().unwrap())
+                .unwrap(),
+            vec![NewS3Object {
+                region: "local".to_string(),
+                bucket: "screenshots".to_string(),
+                key: "key".to_string(),
+            }]
+        );
+    }
+}
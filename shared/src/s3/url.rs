@@ -0,0 +1,109 @@
+use super::NewS3Object;
+
+/// Builds the urls at which objects can be downloaded, provided they're
+/// public.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlBuilder {
+    /// If none, the objects are in AWS.
+    endpoint: Option<String>,
+    /// Whether the bucket is named in the path rather than in the host.
+    path_style: bool,
+}
+
+impl UrlBuilder {
+    pub fn new(endpoint: Option<String>, path_style: bool) -> Self {
+        Self {
+            endpoint: endpoint.map(|e| e.trim_end_matches('/').to_string()),
+            path_style,
+        }
+    }
+
+    pub fn object_url(&self, object: &NewS3Object) -> String {
+        let NewS3Object {
+            region,
+            bucket,
+            key,
+        } = object;
+        let key = encode_path(key);
+
+        match (&self.endpoint, self.path_style) {
+            (None, true) => {
+                format!(
+                    "https://s3-{}.amazonaws.com/{}/{}",
+                    region, bucket, key
+                )
+            }
+            (None, false) => {
+                format!(
+                    "https://{}.s3.{}.amazonaws.com/{}",
+                    bucket, region, key
+                )
+            }
+            (Some(endpoint), true) => {
+                format!("{}/{}/{}", endpoint, bucket, key)
+            }
+            (Some(endpoint), false) => match endpoint.split_once("://") {
+                Some((scheme, host)) => {
+                    format!("{}://{}.{}/{}", scheme, bucket, host, key)
+                }
+                None => format!("{}.{}/{}", bucket, endpoint, key),
+            },
+        }
+    }
+}
+
+/// Path style urls to AWS, the way the services always addressed objects.
+impl Default for UrlBuilder {
+    fn default() -> Self {
+        Self::new(None, true)
+    }
+}
+
+/// Percent encodes everything in the key but unreserved characters and
+/// slashes.
+fn encode_path(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_object_urls() {
+        let object = NewS3Object {
+            region: "eu-west-1".to_string(),
+            bucket: "bucket".to_string(),
+            key: "dir/a b+é".to_string(),
+        };
+
+        assert_eq!(
+            UrlBuilder::default().object_url(&object),
+            "https://s3-eu-west-1.amazonaws.com/bucket/dir/a%20b%2B%C3%A9"
+        );
+        assert_eq!(
+            UrlBuilder::new(None, false).object_url(&object),
+            "https://bucket.s3.eu-west-1.amazonaws.com/dir/a%20b%2B%C3%A9"
+        );
+        assert_eq!(
+            UrlBuilder::new(Some("http://minio:9000/".to_string()), true)
+                .object_url(&object),
+            "http://minio:9000/bucket/dir/a%20b%2B%C3%A9"
+        );
+        assert_eq!(
+            UrlBuilder::new(Some("http://minio:9000".to_string()), false)
+                .object_url(&object),
+            "http://bucket.minio:9000/dir/a%20b%2B%C3%A9"
+        );
+    }
+}
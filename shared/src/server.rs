@@ -0,0 +1,221 @@
+//! A minimal HTTP server for Prometheus to scrape [`metrics`] from on
+//! `/metrics`, and for k8s probes.
+//!
+//! - `/healthz` answers as long as the service's task isn't stuck;
+//! - `/readyz` additionally checks the dependencies of the service, see
+//!   [`Readiness`]. If a check fails, we answer with 503 and the error.
+//!
+//! Requests are answered one at a time in the same task as the worker runs.
+//! They are rare and cheap, therefore we don't need a full blown HTTP
+//! framework.
+
+use crate::{error::Error, metrics};
+use async_trait::async_trait;
+use futures::future;
+use serde::Deserialize;
+use std::{io, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// Slow clients must not block the server for long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// We only read the request line, the rest of the request is ignored.
+const MAX_REQUEST_HEAD_LEN: usize = 8 * 1024;
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// On which port to serve the endpoints.
+    ///
+    /// # Default
+    /// If not set, no server is started.
+    pub http_port: Option<u16>,
+}
+
+/// Implemented by each service's state, which has the clients of the
+/// dependencies.
+#[async_trait(?Send)]
+pub trait Readiness {
+    /// Returns an error if a dependency the service can't work without is
+    /// unusable.
+    async fn check(&self) -> Result<(), Error>;
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: &'static str,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// Answers requests until an error occurs while listening. Never returns if
+/// there's no port to listen on, so that it can be selected along with the
+/// worker.
+pub async fn serve(
+    conf: &Conf,
+    readiness: &impl Readiness,
+) -> Result<(), Error> {
+    let port = match conf.http_port {
+        Some(port) => port,
+        None => return future::pending().await,
+    };
+
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    log::info!("Serving metrics and probes on port {}", port);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let res = time::timeout(REQUEST_TIMEOUT, respond(stream, readiness));
+        match res.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => log::warn!("Cannot respond to {}: {}", addr, e),
+            Err(_) => log::warn!("Request from {} timed out", addr),
+        }
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    readiness: &impl Readiness,
+) -> io::Result<()> {
+    let mut head = vec![0; MAX_REQUEST_HEAD_LEN];
+    let mut len = 0;
+    while !head[..len].windows(2).any(|w| w == b"\r\n") {
+        if len == head.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request line too long",
+            ));
+        }
+
+        let read = stream.read(&mut head[len..]).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        len += read;
+    }
+
+    let res = match request_path(&String::from_utf8_lossy(&head[..len])) {
+        Some(path) => route(path, readiness).await,
+        None => status("400 Bad Request"),
+    };
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                Connection: close\r\n\r\n",
+                res.status,
+                res.content_type,
+                res.body.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.write_all(&res.body).await?;
+    stream.shutdown().await
+}
+
+/// Returns the path of a GET request.
+fn request_path(head: &str) -> Option<&str> {
+    let mut request_line = head.lines().next()?.split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => {
+            // we don't care about the query
+            path.split('?').next()
+        }
+        _ => None,
+    }
+}
+
+async fn route(path: &str, readiness: &impl Readiness) -> Response {
+    match path {
+        "/healthz" => status("200 OK"),
+        "/readyz" => match readiness.check().await {
+            Ok(()) => status("200 OK"),
+            Err(e) => {
+                log::warn!("Not ready: {}", e);
+                Response {
+                    body: e.to_string().into_bytes(),
+                    ..status("503 Service Unavailable")
+                }
+            }
+        },
+        "/metrics" => {
+            let (content_type, body) = metrics::encode();
+            Response {
+                status: "200 OK",
+                content_type,
+                body,
+            }
+        }
+        _ => status("404 Not Found"),
+    }
+}
+
+fn status(status: &'static str) -> Response {
+    Response {
+        status,
+        content_type: "text/plain".to_string(),
+        body: status.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_request_path() {
+        assert_eq!(
+            request_path("GET /metrics HTTP/1.1\r\nHost: localhost\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(
+            request_path("GET /metrics?x=1 HTTP/1.1\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(request_path("POST /metrics HTTP/1.1\r\n"), None);
+        assert_eq!(request_path(""), None);
+    }
+
+    #[tokio::test]
+    async fn it_routes() {
+        assert_eq!(
+            route("/metrics", &ReadinessStub(true)).await.status,
+            "200 OK"
+        );
+        assert_eq!(
+            route("/", &ReadinessStub(true)).await,
+            status("404 Not Found")
+        );
+        assert_eq!(
+            route("/healthz", &ReadinessStub(false)).await,
+            status("200 OK")
+        );
+        assert_eq!(
+            route("/readyz", &ReadinessStub(true)).await,
+            status("200 OK")
+        );
+
+        let res = route("/readyz", &ReadinessStub(false)).await;
+        assert_eq!(res.status, "503 Service Unavailable");
+        assert_eq!(res.body, b"[fatal_infra.other] Gone");
+    }
+
+    struct ReadinessStub(bool);
+
+    #[async_trait(?Send)]
+    impl Readiness for ReadinessStub {
+        async fn check(&self) -> Result<(), Error> {
+            if self.0 {
+                Ok(())
+            } else {
+                Err(Error::fatal("Gone"))
+            }
+        }
+    }
+}
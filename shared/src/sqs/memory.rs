@@ -0,0 +1,362 @@
+//! An in-process queue which behaves like SQS closely enough to run the
+//! [`crate::worker`] against it. Received messages are hidden for the
+//! visibility timeout and delivered again unless they're deleted in the
+//! meantime. Each delivery gets a new receipt handle and increments the
+//! [`APPROXIMATE_RECEIVE_COUNT`] attribute.
+//!
+//! Queues are created on first use.
+
+use super::{SqsExt, APPROXIMATE_RECEIVE_COUNT, MAX_BATCH_SIZE};
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_sqs::{
+    ChangeMessageVisibilityError, DeleteMessageBatchError, DeleteMessageError,
+    GetQueueAttributesError, Message, ReceiveMessageError, SendMessageError,
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::{self, Instant};
+
+/// How often a receive checks for new messages while long polling.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct MemorySqs {
+    visibility_timeout: Duration,
+    /// How long a receive waits for a message before it returns empty.
+    wait_time: Duration,
+    queues: Mutex<HashMap<String, Queue>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// In the order they were sent.
+    messages: Vec<StoredMessage>,
+    next_id: u64,
+}
+
+struct StoredMessage {
+    id: String,
+    body: String,
+    receive_count: i64,
+    visible_at: Instant,
+    /// Only the handle from the latest delivery is valid.
+    receipt_handle: Option<String>,
+}
+
+impl MemorySqs {
+    pub fn new(visibility_timeout: Duration, wait_time: Duration) -> Self {
+        Self {
+            visibility_timeout,
+            wait_time,
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Bodies of all messages which haven't been deleted yet, including the
+    /// ones which are currently invisible.
+    pub fn bodies(&self, queue_url: &str) -> Vec<String> {
+        self.queues
+            .lock()
+            .unwrap()
+            .get(queue_url)
+            .map(|queue| {
+                queue.messages.iter().map(|m| m.body.clone()).collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Takes up to `max` visible messages and hides them.
+    fn take_visible(&self, queue_url: &str, max: usize) -> Vec<Message> {
+        let now = Instant::now();
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(queue_url.to_string()).or_default();
+
+        queue
+            .messages
+            .iter_mut()
+            .filter(|message| message.visible_at <= now)
+            .take(max)
+            .map(|message| {
+                message.receive_count += 1;
+                message.visible_at = now + self.visibility_timeout;
+                let receipt_handle =
+                    format!("{}#{}", message.id, message.receive_count);
+                message.receipt_handle = Some(receipt_handle.clone());
+
+                let mut attributes = HashMap::new();
+                attributes.insert(
+                    APPROXIMATE_RECEIVE_COUNT.to_string(),
+                    message.receive_count.to_string(),
+                );
+                Message {
+                    message_id: Some(message.id.clone()),
+                    receipt_handle: Some(receipt_handle),
+                    body: Some(message.body.clone()),
+                    attributes: Some(attributes),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Calls given function with the message which was last delivered with
+    /// the receipt handle. Returns false if there's no such message.
+    fn with_message(
+        &self,
+        queue_url: &str,
+        receipt_handle: &str,
+        f: impl FnOnce(&mut Queue, usize),
+    ) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(queue_url.to_string()).or_default();
+        let position = queue.messages.iter().position(|message| {
+            message.receipt_handle.as_deref() == Some(receipt_handle)
+        });
+
+        match position {
+            Some(position) => {
+                f(queue, position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&self, queue_url: &str, receipt_handle: &str) -> bool {
+        self.with_message(queue_url, receipt_handle, |queue, position| {
+            queue.messages.remove(position);
+        })
+    }
+}
+
+#[async_trait]
+impl SqsExt for MemorySqs {
+    async fn receive_batch(
+        &self,
+        queue_url: String,
+        max: usize,
+    ) -> Result<Vec<Message>, RusotoError<ReceiveMessageError>> {
+        let deadline = Instant::now() + self.wait_time;
+        loop {
+            let messages =
+                self.take_visible(&queue_url, max.clamp(1, MAX_BATCH_SIZE));
+            if !messages.is_empty() || Instant::now() >= deadline {
+                return Ok(messages);
+            }
+
+            time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn delete(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+    ) -> Result<(), RusotoError<DeleteMessageError>> {
+        if self.remove(&queue_url, &receipt_handle) {
+            Ok(())
+        } else {
+            Err(RusotoError::Service(
+                DeleteMessageError::ReceiptHandleIsInvalid(receipt_handle),
+            ))
+        }
+    }
+
+    async fn delete_batch(
+        &self,
+        queue_url: String,
+        receipt_handles: Vec<String>,
+    ) -> Result<Vec<String>, RusotoError<DeleteMessageBatchError>> {
+        // like a single SQS request
+        if receipt_handles.len() > MAX_BATCH_SIZE {
+            return Err(RusotoError::Service(
+                DeleteMessageBatchError::TooManyEntriesInBatchRequest(
+                    receipt_handles.len().to_string(),
+                ),
+            ));
+        }
+
+        Ok(receipt_handles
+            .into_iter()
+            .filter(|receipt_handle| !self.remove(&queue_url, receipt_handle))
+            .collect())
+    }
+
+    async fn send(
+        &self,
+        queue_url: String,
+        body: String,
+    ) -> Result<(), RusotoError<SendMessageError>> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(queue_url).or_default();
+        queue.next_id += 1;
+        queue.messages.push(StoredMessage {
+            id: queue.next_id.to_string(),
+            body,
+            receive_count: 0,
+            visible_at: Instant::now(),
+            receipt_handle: None,
+        });
+
+        Ok(())
+    }
+
+    async fn get_attributes(
+        &self,
+        _queue_url: String,
+        attrs: Vec<String>,
+    ) -> Result<HashMap<String, String>, RusotoError<GetQueueAttributesError>>
+    {
+        Ok(attrs
+            .into_iter()
+            .filter(|attr| attr == "VisibilityTimeout")
+            .map(|attr| (attr, self.visibility_timeout.as_secs().to_string()))
+            .collect())
+    }
+
+    async fn change_visibility(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+        timeout: Duration,
+    ) -> Result<(), RusotoError<ChangeMessageVisibilityError>> {
+        let changed = self.with_message(
+            &queue_url,
+            &receipt_handle,
+            |queue, position| {
+                queue.messages[position].visible_at = Instant::now() + timeout;
+            },
+        );
+
+        if changed {
+            Ok(())
+        } else {
+            Err(RusotoError::Service(
+                ChangeMessageVisibilityError::ReceiptHandleIsInvalid(
+                    receipt_handle,
+                ),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqs::receive_count;
+
+    const QUEUE_URL: &str = "queue";
+
+    #[tokio::test]
+    async fn it_redelivers_messages_which_are_not_deleted() {
+        time::pause();
+        let sqs =
+            MemorySqs::new(Duration::from_secs(30), Duration::from_secs(20));
+
+        sqs.send(QUEUE_URL.to_string(), "first".to_string())
+            .await
+            .unwrap();
+        sqs.send(QUEUE_URL.to_string(), "second".to_string())
+            .await
+            .unwrap();
+
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 1).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body.as_deref(), Some("first"));
+        assert_eq!(receive_count(&messages[0]), Some(1));
+        let first_handle = messages[0].receipt_handle.clone().unwrap();
+
+        // the first message is hidden
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 10).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body.as_deref(), Some("second"));
+        sqs.delete(
+            QUEUE_URL.to_string(),
+            messages[0].receipt_handle.clone().unwrap(),
+        )
+        .await
+        .unwrap();
+
+        // nothing is visible until the visibility timeout of the first message
+        // elapses, which is after the wait time
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 10).await.unwrap();
+        assert!(messages.is_empty());
+
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 10).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body.as_deref(), Some("first"));
+        assert_eq!(receive_count(&messages[0]), Some(2));
+
+        // the handle from the first delivery is no longer valid
+        assert_eq!(
+            sqs.delete_batch(
+                QUEUE_URL.to_string(),
+                vec![
+                    first_handle.clone(),
+                    messages[0].receipt_handle.clone().unwrap()
+                ]
+            )
+            .await
+            .unwrap(),
+            vec![first_handle]
+        );
+        assert!(sqs.bodies(QUEUE_URL).is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_extends_visibility() {
+        time::pause();
+        let sqs =
+            MemorySqs::new(Duration::from_secs(30), Duration::from_secs(20));
+        sqs.send(QUEUE_URL.to_string(), "body".to_string())
+            .await
+            .unwrap();
+
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 1).await.unwrap();
+        sqs.change_visibility(
+            QUEUE_URL.to_string(),
+            messages[0].receipt_handle.clone().unwrap(),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+
+        // 40s have passed, which is after the original timeout
+        for _ in 0..2 {
+            let messages =
+                sqs.receive_batch(QUEUE_URL.to_string(), 1).await.unwrap();
+            assert!(messages.is_empty());
+        }
+        let messages =
+            sqs.receive_batch(QUEUE_URL.to_string(), 1).await.unwrap();
+        assert_eq!(receive_count(&messages[0]), Some(2));
+
+        let attrs = sqs
+            .get_attributes(
+                QUEUE_URL.to_string(),
+                vec!["VisibilityTimeout".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(attrs.get("VisibilityTimeout").unwrap(), "30");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_too_large_delete_batch() {
+        let sqs =
+            MemorySqs::new(Duration::from_secs(30), Duration::from_secs(0));
+        let receipt_handles = (0..=MAX_BATCH_SIZE).map(|i| i.to_string());
+
+        assert!(matches!(
+            sqs.delete_batch(QUEUE_URL.to_string(), receipt_handles.collect())
+                .await,
+            Err(RusotoError::Service(
+                DeleteMessageBatchError::TooManyEntriesInBatchRequest(_)
+            ))
+        ));
+    }
+}
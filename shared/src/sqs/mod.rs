@@ -0,0 +1,235 @@
+pub mod memory;
+
+use {
+    crate::aws,
+    async_trait::async_trait,
+    rusoto_core::{Region, RusotoError},
+    rusoto_sqs::{
+        ChangeMessageVisibilityError, ChangeMessageVisibilityRequest,
+        DeleteMessageBatchError, DeleteMessageBatchRequest,
+        DeleteMessageBatchRequestEntry, DeleteMessageError,
+        DeleteMessageRequest, GetQueueAttributesError,
+        GetQueueAttributesRequest, Message, ReceiveMessageError,
+        ReceiveMessageRequest, SendMessageError, SendMessageRequest, Sqs,
+        SqsClient,
+    },
+    serde::Deserialize,
+    std::{collections::HashMap, time::Duration},
+};
+
+/// How many times has a message been received from the queue without being
+/// deleted.
+pub const APPROXIMATE_RECEIVE_COUNT: &str = "ApproximateReceiveCount";
+
+/// SQS won't return more messages per receive, nor delete more messages per
+/// batch request.
+pub const MAX_BATCH_SIZE: usize = 10;
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// Sends SQS requests to this url instead of AWS, e.g.
+    /// `http://localhost:9324` for ElasticMQ.
+    pub sqs_endpoint: Option<String>,
+}
+
+impl Conf {
+    pub fn client(&self, region: Region) -> SqsClient {
+        SqsClient::new(aws::region(region, self.sqs_endpoint.as_deref()))
+    }
+}
+
+/// Implements only methods which this project requires instead of all
+/// [`rusoto_sqs::Sqs`] methods, which makes it more comfortable to write stubs
+/// and test it.
+#[async_trait]
+pub trait SqsExt {
+    /// Long polls the queue for up to `max` messages, at most
+    /// [`MAX_BATCH_SIZE`]. Returns an empty vec if no message arrived in time.
+    async fn receive_batch(
+        &self,
+        queue_url: String,
+        max: usize,
+    ) -> Result<Vec<Message>, RusotoError<ReceiveMessageError>>;
+
+    async fn delete(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+    ) -> Result<(), RusotoError<DeleteMessageError>>;
+
+    /// Returns receipt handles of those messages which couldn't be deleted.
+    /// SQS deletes at most [`MAX_BATCH_SIZE`] messages per request, the
+    /// [`SqsClient`] splits larger batches into several requests.
+    async fn delete_batch(
+        &self,
+        queue_url: String,
+        receipt_handles: Vec<String>,
+    ) -> Result<Vec<String>, RusotoError<DeleteMessageBatchError>>;
+
+    async fn send(
+        &self,
+        queue_url: String,
+        body: String,
+    ) -> Result<(), RusotoError<SendMessageError>>;
+
+    async fn get_attributes(
+        &self,
+        queue_url: String,
+        attrs: Vec<String>,
+    ) -> Result<HashMap<String, String>, RusotoError<GetQueueAttributesError>>;
+
+    /// Makes the message invisible to other consumers for given duration,
+    /// counting from now.
+    async fn change_visibility(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+        timeout: Duration,
+    ) -> Result<(), RusotoError<ChangeMessageVisibilityError>>;
+}
+
+/// The brick and bones of any SQS listening microservice. Keeps on receiving
+/// messages from an sqs in a loop. On an unrecoverable error, stops everything
+/// and returns it.
+#[async_trait]
+impl SqsExt for SqsClient {
+    async fn receive_batch(
+        &self,
+        queue_url: String,
+        max: usize,
+    ) -> Result<Vec<Message>, RusotoError<ReceiveMessageError>> {
+        let req = ReceiveMessageRequest {
+            queue_url,
+            max_number_of_messages: Some(max.clamp(1, MAX_BATCH_SIZE) as i64),
+            wait_time_seconds: Some(20), // max
+            attribute_names: Some(vec![APPROXIMATE_RECEIVE_COUNT.to_string()]),
+            ..Default::default()
+        };
+
+        Ok(self
+            .receive_message(req)
+            .await?
+            .messages
+            .unwrap_or_default())
+    }
+
+    async fn delete(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+    ) -> Result<(), RusotoError<DeleteMessageError>> {
+        let req = DeleteMessageRequest {
+            receipt_handle,
+            queue_url,
+        };
+        self.delete_message(req).await?;
+        Ok(())
+    }
+
+    async fn delete_batch(
+        &self,
+        queue_url: String,
+        receipt_handles: Vec<String>,
+    ) -> Result<Vec<String>, RusotoError<DeleteMessageBatchError>> {
+        let mut not_deleted = vec![];
+        // SQS rejects requests with more entries
+        for chunk in receipt_handles.chunks(MAX_BATCH_SIZE) {
+            // the id only needs to be unique within the request
+            let entries = chunk
+                .iter()
+                .enumerate()
+                .map(|(index, receipt_handle)| DeleteMessageBatchRequestEntry {
+                    id: index.to_string(),
+                    receipt_handle: receipt_handle.clone(),
+                })
+                .collect();
+            let req = DeleteMessageBatchRequest {
+                entries,
+                queue_url: queue_url.clone(),
+            };
+            let res = self.delete_message_batch(req).await?;
+
+            not_deleted.extend(res.failed.into_iter().filter_map(|entry| {
+                log::warn!("Cannot delete message: {:?}", entry);
+                chunk.get(entry.id.parse::<usize>().ok()?).cloned()
+            }));
+        }
+
+        Ok(not_deleted)
+    }
+
+    async fn send(
+        &self,
+        queue_url: String,
+        body: String,
+    ) -> Result<(), RusotoError<SendMessageError>> {
+        let req = SendMessageRequest {
+            queue_url,
+            message_body: body,
+            ..Default::default()
+        };
+        self.send_message(req).await?;
+        Ok(())
+    }
+
+    async fn get_attributes(
+        &self,
+        queue_url: String,
+        attrs: Vec<String>,
+    ) -> Result<HashMap<String, String>, RusotoError<GetQueueAttributesError>>
+    {
+        let req = GetQueueAttributesRequest {
+            queue_url,
+            attribute_names: Some(attrs),
+        };
+        let res = self.get_queue_attributes(req).await?.attributes;
+        Ok(res.unwrap_or_default())
+    }
+
+    async fn change_visibility(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+        timeout: Duration,
+    ) -> Result<(), RusotoError<ChangeMessageVisibilityError>> {
+        let req = ChangeMessageVisibilityRequest {
+            queue_url,
+            receipt_handle,
+            visibility_timeout: timeout.as_secs() as i64,
+        };
+        self.change_message_visibility(req).await?;
+        Ok(())
+    }
+}
+
+/// Returns how many times has the message been received, if we asked SQS for
+/// the attribute.
+pub fn receive_count(message: &Message) -> Option<i64> {
+    message
+        .attributes
+        .as_ref()?
+        .get(APPROXIMATE_RECEIVE_COUNT)?
+        .parse()
+        .ok()
+}
+
+/// Returns the visibility timeout of the input SQS. That is, returns the
+/// duration of how long a message is "invisible" to other consumers after it's
+/// received.
+pub async fn get_visibility_timeout(
+    sqs: &dyn SqsExt,
+    queue_url: String,
+) -> Result<Option<Duration>, RusotoError<GetQueueAttributesError>> {
+    log::info!("Requesting visibility timeout from SQS {}", queue_url);
+    let attrs = sqs
+        .get_attributes(queue_url, vec!["VisibilityTimeout".to_string()])
+        .await?;
+    log::trace!("Queue attrs: {:#?}", attrs);
+    attrs
+        .get("VisibilityTimeout")
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .map(Ok)
+        .transpose()
+}
@@ -0,0 +1,162 @@
+//! Correlates what the services log about one newsletter. All services store
+//! their artifacts under the same S3 key, therefore we derive a trace id from
+//! the key. The worker handles each message within a [`span`] which carries the
+//! trace id, and whose OpenTelemetry trace is the same in every service. One
+//! query by the trace id then shows the whole life of an email.
+//!
+//! The trace id is also stored in the metadata of the objects the services put,
+//! see [`crate::s3::PutConf::with_trace_id`].
+//!
+//! Logs are JSON lines with `LOG_FORMAT=json`, otherwise human readable. The
+//! `log` macros keep working and `RUST_LOG` still sets the level. Spans are
+//! exported to an OpenTelemetry collector if `OTEL_EXPORTER_OTLP_ENDPOINT` is
+//! set.
+
+use crate::error::Error;
+use opentelemetry::{
+    sdk::{trace, Resource},
+    trace::{
+        SpanContext, SpanId, TraceContextExt, TraceId, TraceState,
+        TRACE_FLAG_SAMPLED,
+    },
+    Context, KeyValue,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
+
+/// Under this key the trace id is stored in S3 object metadata.
+pub const TRACE_ID_METADATA_KEY: &str = "trace-id";
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// # Default
+    /// Text
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Where the OpenTelemetry collector listens for OTLP.
+    ///
+    /// # Default
+    /// If not set, spans aren't exported.
+    pub otel_exporter_otlp_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+/// Installs the global logger and tracer. Must be called from within the
+/// tokio runtime, which exports the spans.
+pub fn init(service_name: &'static str, conf: &Conf) -> Result<(), Error> {
+    let otel = match &conf.otel_exporter_otlp_endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .with_endpoint(endpoint)
+                .with_trace_config(trace::config().with_resource(
+                    Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        service_name,
+                    )]),
+                ))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .map_err(|e| Error::fatal(e).with_code("telemetry"))?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+    let json = conf.log_format == LogFormat::Json;
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(json.then(|| fmt::layer().json()))
+        .with((!json).then(fmt::layer))
+        .with(otel)
+        .try_init()
+        .map_err(|e| Error::fatal(e).with_code("telemetry"))
+}
+
+/// Exports the spans which haven't been exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Hex encoded, 32 characters long as OpenTelemetry trace ids are.
+pub fn trace_id(s3_key: &str) -> String {
+    format!("{:032x}", ids(s3_key).0)
+}
+
+/// The span within which a service handles given object. Its parent is a
+/// remote span derived from the key too, therefore the spans of all services
+/// end up in the same trace.
+pub fn span(s3_key: &str) -> Span {
+    let (trace_id, span_id) = ids(s3_key);
+    let span = tracing::info_span!(
+        "handle",
+        trace_id = %format!("{:032x}", trace_id),
+        s3_key
+    );
+
+    let parent = SpanContext::new(
+        TraceId::from_u128(trace_id),
+        SpanId::from_u64(span_id),
+        TRACE_FLAG_SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    span.set_parent(Context::new().with_remote_span_context(parent));
+
+    span
+}
+
+/// Objects handled together can belong to different traces, the span lists
+/// all their trace ids.
+pub fn batch_span<'a>(s3_keys: impl Iterator<Item = &'a str>) -> Span {
+    let trace_ids: Vec<_> = s3_keys.map(trace_id).collect();
+    tracing::info_span!("handle_batch", trace_ids = ?trace_ids)
+}
+
+/// Trace id and parent span id. Sha256 is stable across builds unlike the std
+/// hasher.
+fn ids(s3_key: &str) -> (u128, u64) {
+    let hash = Sha256::digest(s3_key.as_bytes());
+    let trace_id = u128::from_be_bytes(
+        hash[..16].try_into().expect("Sha256 has 32 bytes"),
+    );
+    let span_id = u64::from_be_bytes(
+        hash[16..24].try_into().expect("Sha256 has 32 bytes"),
+    );
+
+    (trace_id, span_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_derives_trace_id_from_key() {
+        let trace_id = trace_id("a6bhcr8nm7vp4hnd2ol6kujv5qf9mvh2");
+
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(
+            trace_id,
+            super::trace_id("a6bhcr8nm7vp4hnd2ol6kujv5qf9mvh2")
+        );
+        assert_ne!(trace_id, super::trace_id("another_key"));
+    }
+}
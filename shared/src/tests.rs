@@ -1,11 +1,19 @@
-use super::{http, S3Ext, SqsExt};
+use super::{
+    http,
+    s3::{NewS3Object, ObjectHead},
+    S3Ext, SqsExt,
+};
 use async_trait::async_trait;
 use rusoto_core::RusotoError;
-use rusoto_s3::{GetObjectError, PutObjectError};
+use rusoto_s3::{
+    DeleteObjectError, GetObjectError, HeadObjectError, ListObjectsV2Error,
+    PutObjectError,
+};
 use rusoto_sqs::{
-    DeleteMessageError, GetQueueAttributesError, Message, ReceiveMessageError,
+    ChangeMessageVisibilityError, DeleteMessageBatchError, DeleteMessageError,
+    GetQueueAttributesError, Message, ReceiveMessageError, SendMessageError,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 #[derive(Default)]
 pub struct S3Stub {
@@ -54,14 +62,56 @@ impl S3Ext for S3Stub {
         assert_eq!(key, self.key);
         Ok(serde_json::from_value(self.object_json.clone()).unwrap())
     }
+
+    async fn get_at_most(
+        &self,
+        bucket: String,
+        key: String,
+        _: usize,
+    ) -> Result<Option<Vec<u8>>, RusotoError<GetObjectError>> {
+        self.get(bucket, key).await
+    }
+
+    async fn head(
+        &self,
+        _: String,
+        _: String,
+    ) -> Result<Option<ObjectHead>, RusotoError<HeadObjectError>> {
+        unimplemented!()
+    }
+
+    async fn delete(
+        &self,
+        _: String,
+        _: String,
+    ) -> Result<(), RusotoError<DeleteObjectError>> {
+        unimplemented!()
+    }
+
+    async fn list_prefix(
+        &self,
+        _: String,
+        _: String,
+    ) -> Result<Vec<String>, RusotoError<ListObjectsV2Error>> {
+        unimplemented!()
+    }
+
+    async fn presigned_url(
+        &self,
+        object: &NewS3Object,
+        _: Duration,
+    ) -> Result<String, RusotoError<GetObjectError>> {
+        Ok(self.object_url(object))
+    }
 }
 
 #[async_trait]
 impl SqsExt for SqsStub {
-    async fn receive_one(
+    async fn receive_batch(
         &self,
         _: String,
-    ) -> Result<Option<Message>, RusotoError<ReceiveMessageError>> {
+        _: usize,
+    ) -> Result<Vec<Message>, RusotoError<ReceiveMessageError>> {
         unimplemented!()
     }
 
@@ -75,6 +125,24 @@ impl SqsExt for SqsStub {
         Ok(())
     }
 
+    async fn delete_batch(
+        &self,
+        queue_url: String,
+        receipt_handles: Vec<String>,
+    ) -> Result<Vec<String>, RusotoError<DeleteMessageBatchError>> {
+        assert_eq!(queue_url, self.queue_url);
+        assert!(receipt_handles.iter().all(|h| h == &self.receipt_handle));
+        Ok(vec![])
+    }
+
+    async fn send(
+        &self,
+        _queue_url: String,
+        _body: String,
+    ) -> Result<(), RusotoError<SendMessageError>> {
+        unimplemented!()
+    }
+
     async fn get_attributes(
         &self,
         _queue_url: String,
@@ -83,6 +151,17 @@ impl SqsExt for SqsStub {
     {
         unimplemented!()
     }
+
+    async fn change_visibility(
+        &self,
+        queue_url: String,
+        receipt_handle: String,
+        _timeout: Duration,
+    ) -> Result<(), RusotoError<ChangeMessageVisibilityError>> {
+        assert_eq!(queue_url, self.queue_url);
+        assert_eq!(receipt_handle, self.receipt_handle);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -91,7 +170,7 @@ impl http::Client for HttpClientStub {
         &self,
         url: &str,
         body: &serde_json::Value,
-    ) -> Result<Vec<u8>, reqwest::Error> {
+    ) -> Result<Vec<u8>, crate::error::Error> {
         assert_eq!(url, self.url);
         assert_eq!(body, &self.body);
 
@@ -9,7 +9,10 @@ pub struct Annotation {
 
 /// Since there are many words in each text, during serialization we rename each
 /// attribute so that when we inpect the generated JSON, it's less cluttered.
-#[derive(Serialize, Deserialize, Hash, Debug, PartialEq, Default, Clone)]
+///
+/// The optional attributes are missing in documents annotated before they were
+/// introduced, and not every OCR provides them.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct Word {
     #[serde(rename = "w")]
     pub word: String,
@@ -17,6 +20,16 @@ pub struct Word {
     pub top_left: Point,
     #[serde(rename = "br")]
     pub bottom_right: Point,
+    /// Index of the block the OCR put the word into, unique in the annotation.
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<u32>,
+    /// Index of the paragraph the OCR put the word into, unique in the
+    /// annotation.
+    #[serde(rename = "p", default, skip_serializing_if = "Option::is_none")]
+    pub paragraph: Option<u32>,
+    /// How sure is the OCR about the word, between 0 and 1.
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
 }
 
 #[derive(
@@ -30,17 +43,30 @@ pub struct Point {
 impl Annotation {
     pub fn from(annotation: GAnnotation) -> Option<Self> {
         let text = annotation.text?;
-        let words = annotation
+        let blocks = annotation
             .pages?
             .into_iter()
             .filter_map(|p| p.blocks)
-            .flatten()
-            .filter_map(|b| b.paragraphs)
-            .flatten()
-            .filter_map(|p| p.words)
-            .flatten()
-            .filter_map(Word::from)
-            .collect();
+            .flatten();
+
+        let mut words = vec![];
+        let mut paragraph_index = 0;
+        for (block_index, block) in blocks.enumerate() {
+            for paragraph in block.paragraphs.unwrap_or_default() {
+                let paragraph_words = paragraph
+                    .words
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(Word::from)
+                    .map(|w| Word {
+                        block: Some(block_index as u32),
+                        paragraph: Some(paragraph_index),
+                        ..w
+                    });
+                words.extend(paragraph_words);
+                paragraph_index += 1;
+            }
+        }
 
         Some(Self { text, words })
     }
@@ -55,6 +81,7 @@ impl Word {
         let left = vertices.iter().min_by(|a, b| a.x.cmp(&b.x))?.x?;
         let right = vertices.iter().max_by(|a, b| a.x.cmp(&b.x))?.x?;
 
+        let confidence = word.confidence;
         // and collects all the symbols of the word
         let text: String =
             word.symbols?.into_iter().filter_map(|s| s.text).collect();
@@ -66,6 +93,8 @@ impl Word {
                 y: bottom,
                 x: right,
             },
+            confidence,
+            ..Default::default()
         })
     }
 }
@@ -114,7 +143,8 @@ mod tests {
                     {"x": 5, "y": 4}
                 ]
             },
-            "symbols": [{"text": "h"}, {"text": "w"}]
+            "symbols": [{"text": "h"}, {"text": "w"}],
+            "confidence": 0.5
         }))
         .unwrap();
 
@@ -123,11 +153,26 @@ mod tests {
                 word: "hw".to_string(),
                 top_left: Point { x: 1, y: 0 },
                 bottom_right: Point { x: 5, y: 5 },
+                confidence: Some(0.5),
+                ..Default::default()
             }),
             Word::from(word)
         );
     }
 
+    #[test]
+    fn it_reads_and_writes_words_without_optional_attributes() {
+        let json =
+            json!({"w": "hw", "tl": {"x": 1, "y": 0}, "br": {"x": 5, "y": 5}});
+
+        let word: Word = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(word.block, None);
+        assert_eq!(word.paragraph, None);
+        assert_eq!(word.confidence, None);
+
+        assert_eq!(serde_json::to_value(&word).unwrap(), json);
+    }
+
     #[test]
     fn it_ignores_no_pages_or_text() {
         let annotation: GAnnotation = serde_json::from_value(json!({
@@ -168,26 +213,63 @@ mod tests {
         }))
         .unwrap();
 
-        let gen_word = |t: &str| Word {
+        let gen_word = |t: &str, block, paragraph| Word {
             word: t.to_string(),
             top_left: Point { x: 0, y: 0 },
             bottom_right: Point { x: 0, y: 0 },
+            block: Some(block),
+            paragraph: Some(paragraph),
+            confidence: None,
         };
         assert_eq!(
             Some(Annotation {
                 text: "1 2 3 4 5 6 7 8".to_string(),
                 words: vec![
-                    gen_word("1"),
-                    gen_word("2"),
-                    gen_word("3"),
-                    gen_word("4"),
-                    gen_word("5"),
-                    gen_word("6"),
-                    gen_word("7"),
-                    gen_word("8"),
+                    gen_word("1", 0, 0),
+                    gen_word("2", 0, 0),
+                    gen_word("3", 1, 1),
+                    gen_word("4", 1, 1),
+                    gen_word("5", 2, 2),
+                    gen_word("6", 2, 2),
+                    gen_word("7", 3, 3),
+                    gen_word("8", 3, 3),
                 ]
             }),
             Annotation::from(annotation)
         );
     }
+
+    #[test]
+    fn it_numbers_paragraphs_across_blocks() {
+        let gen_word = |t| {
+            json!({
+                "boundingBox": {
+                    "vertices": [{"x": 0, "y": 0}]
+                },
+                "symbols": [{"text": t}]
+            })
+        };
+        let annotation: GAnnotation = serde_json::from_value(json!({
+            "text": "1 2 3",
+            "pages": [
+                {"blocks": [
+                    {"paragraphs": [
+                        {"words": [gen_word("1")]},
+                        {"words": [gen_word("2")]}
+                    ]},
+                    {"paragraphs": [{"words": [gen_word("3")]}]}
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        let words = Annotation::from(annotation).unwrap().words;
+        assert_eq!(
+            words
+                .iter()
+                .map(|w| (w.block, w.paragraph))
+                .collect::<Vec<_>>(),
+            vec![(Some(0), Some(0)), (Some(0), Some(1)), (Some(1), Some(2))]
+        );
+    }
 }
@@ -0,0 +1,1234 @@
+//! The brick and bones of any SQS listening microservice. Keeps on receiving
+//! messages from an SQS in a loop, decodes each of them into a
+//! [`NewS3Object`] and passes it to the service's [`Handler`].
+//!
+//! When the handler succeeds, the message is deleted from the queue. On a
+//! recoverable error, the error is logged and the message is left in the queue.
+//! On an unrecoverable error, the worker stops everything and returns it. We
+//! rely on supervision, such as k8s controller, that restarts failed jobs.
+//!
+//! By default the worker handles one message at a time. With
+//! [`Conf::concurrency`] it receives a batch of messages and handles them
+//! concurrently within the same task. Handled messages are deleted with a
+//! single batch request.
+//!
+//! While the handler is working on a message, the worker keeps extending the
+//! visibility timeout of the message so that it's not received by another
+//! consumer.
+//!
+//! A message which keeps failing with a recoverable error is eventually given
+//! up on. See [`Conf::max_receive_count`].
+//!
+//! Services which benefit from handling several messages at once, such as
+//! `ocr` stitching screenshots together, implement [`BatchHandler`] and call
+//! [`run_batch`] instead. The worker then keeps on receiving messages until it
+//! has [`Conf::max_batch_len`] of them or until the oldest one has been held for
+//! half of the visibility timeout, whichever comes first.
+//!
+//! A message can tell us about several objects, or about none in case of the
+//! S3 test event. The objects of a message are handled one after another and
+//! the message is done once all of them are. See [`NewS3Object::from_message`]
+//! for the message formats we accept.
+//!
+//! Each object is handled within a span which carries its trace id, see
+//! [`telemetry`].
+//!
+//! On SIGTERM the worker finishes the messages it's currently handling and
+//! returns.
+
+use crate::{
+    failed_jobs::FailedJobs,
+    metrics,
+    s3::NewS3Object,
+    sqs::{self, SqsExt},
+    telemetry,
+};
+use async_trait::async_trait;
+use futures::future;
+use rusoto_core::RusotoError;
+use rusoto_sqs::{
+    DeleteMessageBatchError, GetQueueAttributesError, Message,
+    ReceiveMessageError, SendMessageError,
+};
+use serde::Deserialize;
+use std::{fmt::Display, io, marker::PhantomData, time::Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time,
+};
+use tracing::Instrument;
+
+/// If the queue doesn't tell us its visibility timeout, we assume the SQS
+/// default.
+const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Name of each env var is the same as the property but in ALL_CAPS.
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct Conf {
+    /// How many messages are handled in parallel. Since SQS returns at most
+    /// [`sqs::MAX_BATCH_SIZE`] messages per receive, that's also the upper
+    /// limit.
+    ///
+    /// # Default
+    /// One message at a time.
+    pub concurrency: Option<usize>,
+    /// After how many receives of a message which keeps failing with a
+    /// recoverable error we give up on it. The message is then moved to the
+    /// dead-letter queue and recorded in the failed jobs table, if those are
+    /// configured, and deleted from the input queue.
+    ///
+    /// # Default
+    /// If not set, the message is retried until it expires from the queue.
+    pub max_receive_count: Option<i64>,
+    /// Where to send the messages we gave up on.
+    pub dead_letter_queue_url: Option<String>,
+    /// Path to the sqlite3 file with the `failed_jobs` table.
+    pub failed_jobs_database_path: Option<String>,
+    /// Only used by [`run_batch`]. How many messages are collected at most
+    /// before they're passed to the [`BatchHandler`].
+    ///
+    /// # Default
+    /// [`sqs::MAX_BATCH_SIZE`]
+    pub max_batch_len: Option<usize>,
+}
+
+impl Conf {
+    fn batch_size(&self) -> usize {
+        self.concurrency.unwrap_or(1).clamp(1, sqs::MAX_BATCH_SIZE)
+    }
+
+    fn max_batch_len(&self) -> usize {
+        self.max_batch_len.unwrap_or(sqs::MAX_BATCH_SIZE).max(1)
+    }
+}
+
+/// Each service implements this trait to process the objects whose insertion
+/// into an S3 bucket triggered the SQS message.
+#[async_trait(?Send)]
+pub trait Handler {
+    type Error: HandlerError;
+
+    async fn handle(&self, record: NewS3Object) -> Result<(), Self::Error>;
+}
+
+/// Like [`Handler`], but all objects collected by the worker are handled at
+/// once. See [`run_batch`].
+#[async_trait(?Send)]
+pub trait BatchHandler {
+    type Error: HandlerError;
+
+    /// Must return exactly one result per record, in the same order as the
+    /// records.
+    async fn handle_batch(
+        &self,
+        records: Vec<NewS3Object>,
+    ) -> Vec<Result<(), Self::Error>>;
+}
+
+/// The worker must be able to tell whether it should keep polling after an
+/// error, and it must be able to construct errors for malformed messages.
+pub trait HandlerError:
+    Display
+    + From<serde_json::Error>
+    + From<io::Error>
+    + From<RusotoError<ReceiveMessageError>>
+    + From<RusotoError<DeleteMessageBatchError>>
+    + From<RusotoError<GetQueueAttributesError>>
+    + From<RusotoError<SendMessageError>>
+{
+    /// This error gets logged but service continues polling sqs.
+    fn new(reason: impl Display) -> Self;
+
+    /// The service terminates.
+    fn fatal(reason: impl Display) -> Self;
+
+    fn is_recoverable(&self) -> bool;
+
+    /// Stable identifier of the kind of error, which is persisted with failed
+    /// jobs.
+    fn code(&self) -> Option<String> {
+        None
+    }
+
+    /// If set, a message which failed with a recoverable error is retried
+    /// after this long instead of after its visibility timeout.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Polls the queue until either an unrecoverable error occurs or the process
+/// receives SIGTERM.
+pub async fn run<H: Handler>(
+    sqs: &dyn SqsExt,
+    queue_url: String,
+    conf: &Conf,
+    handler: &H,
+) -> Result<(), H::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let failed_jobs = open_failed_jobs::<H::Error>(conf)?;
+    let worker =
+        Worker::<H::Error>::new(sqs, &queue_url, conf, failed_jobs.as_ref())
+            .await?;
+
+    loop {
+        log::trace!("Waiting for new messages");
+        let messages = tokio::select! {
+            _ = sigterm.recv() => {
+                log::info!("Received SIGTERM, shutting down");
+                return Ok(());
+            }
+            messages = sqs.receive_batch(
+                queue_url.clone(), conf.batch_size()
+            ) => messages?,
+        };
+
+        worker.process_batch(handler, &messages).await?;
+    }
+}
+
+/// Like [`run`], but collects messages into batches for the [`BatchHandler`].
+pub async fn run_batch<H: BatchHandler>(
+    sqs: &dyn SqsExt,
+    queue_url: String,
+    conf: &Conf,
+    handler: &H,
+) -> Result<(), H::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let failed_jobs = open_failed_jobs::<H::Error>(conf)?;
+    let worker =
+        Worker::<H::Error>::new(sqs, &queue_url, conf, failed_jobs.as_ref())
+            .await?;
+    let max_batch_len = conf.max_batch_len();
+
+    loop {
+        log::trace!("Waiting for new messages");
+        let mut messages = tokio::select! {
+            _ = sigterm.recv() => {
+                log::info!("Received SIGTERM, shutting down");
+                return Ok(());
+            }
+            messages = sqs.receive_batch(
+                queue_url.clone(), max_batch_len
+            ) => messages?,
+        };
+        if messages.is_empty() {
+            continue;
+        }
+
+        // the heartbeat only starts once the batch is being handled, so we
+        // must not hold the messages for longer than their visibility timeout
+        let deadline = time::Instant::now() + worker.visibility_timeout / 2;
+        while messages.len() < max_batch_len {
+            let more = time::timeout_at(
+                deadline,
+                sqs.receive_batch(
+                    queue_url.clone(),
+                    max_batch_len - messages.len(),
+                ),
+            )
+            .await;
+            match more {
+                Ok(more) => {
+                    let more = more?;
+                    if more.is_empty() {
+                        // no new messages in the whole long poll
+                        break;
+                    }
+                    messages.extend(more);
+                }
+                // if the request was cancelled after SQS had already sent us
+                // some messages, they become visible again once their
+                // visibility timeout runs out
+                Err(_) => break,
+            }
+        }
+
+        log::debug!("Collected a batch of {} messages", messages.len());
+        worker.process_together(handler, &messages).await?;
+    }
+}
+
+fn open_failed_jobs<E: HandlerError>(
+    conf: &Conf,
+) -> Result<Option<FailedJobs>, E> {
+    conf.failed_jobs_database_path
+        .as_deref()
+        .map(FailedJobs::open)
+        .transpose()
+        .map_err(E::fatal)
+}
+
+/// Everything the worker needs to process received messages.
+struct Worker<'a, E> {
+    sqs: &'a dyn SqsExt,
+    queue_url: &'a str,
+    visibility_timeout: Duration,
+    conf: &'a Conf,
+    failed_jobs: Option<&'a FailedJobs>,
+    _error: PhantomData<E>,
+}
+
+impl<'a, E: HandlerError> Worker<'a, E> {
+    async fn new(
+        sqs: &'a dyn SqsExt,
+        queue_url: &'a str,
+        conf: &'a Conf,
+        failed_jobs: Option<&'a FailedJobs>,
+    ) -> Result<Worker<'a, E>, E> {
+        let visibility_timeout =
+            sqs::get_visibility_timeout(sqs, queue_url.to_string())
+                .await?
+                .unwrap_or(DEFAULT_VISIBILITY_TIMEOUT);
+
+        Ok(Self {
+            sqs,
+            queue_url,
+            visibility_timeout,
+            conf,
+            failed_jobs,
+            _error: PhantomData,
+        })
+    }
+
+    /// Processes all messages concurrently and then deletes in one request
+    /// those which were either handled or given up on.
+    ///
+    /// Returns the first unrecoverable error, if any, but only after the other
+    /// messages in the batch finished.
+    async fn process_batch<H: Handler<Error = E>>(
+        &self,
+        handler: &H,
+        messages: &[Message],
+    ) -> Result<(), E> {
+        metrics::MESSAGES_RECEIVED.inc_by(messages.len() as u64);
+        let results =
+            future::join_all(messages.iter().map(|m| self.process(handler, m)))
+                .await;
+
+        self.settle(messages, results).await
+    }
+
+    /// Decodes all messages and passes their records to the handler in one
+    /// call, while keeping all of them invisible to other consumers.
+    async fn process_together<H: BatchHandler<Error = E>>(
+        &self,
+        handler: &H,
+        messages: &[Message],
+    ) -> Result<(), E> {
+        metrics::MESSAGES_RECEIVED.inc_by(messages.len() as u64);
+        let mut results = Vec::with_capacity(messages.len());
+        let mut records = Vec::with_capacity(messages.len());
+        // which message does each record belong to
+        let mut indexes = Vec::with_capacity(messages.len());
+        // messages which have at least one record
+        let mut held = Vec::with_capacity(messages.len());
+        for (index, message) in messages.iter().enumerate() {
+            match decode::<E>(message) {
+                Ok((_, message_records)) => {
+                    if !message_records.is_empty() {
+                        held.push(index);
+                    }
+                    for record in message_records {
+                        records.push(record);
+                        indexes.push(index);
+                    }
+                    results.push(Ok(()));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        if !records.is_empty() {
+            let heartbeats = held.iter().filter_map(|index| {
+                let receipt_handle =
+                    messages[*index].receipt_handle.as_ref()?;
+                Some(heartbeat(
+                    self.sqs,
+                    self.queue_url,
+                    receipt_handle,
+                    self.visibility_timeout,
+                ))
+            });
+
+            let span =
+                telemetry::batch_span(records.iter().map(|r| r.key.as_str()));
+            let timer = metrics::HANDLER_DURATION.start_timer();
+            let handled = async {
+                tokio::select! {
+                    biased;
+                    handled = handler.handle_batch(records) => handled,
+                    _ = future::join_all(heartbeats) => {
+                        unreachable!("Heartbeat never finishes")
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+            timer.observe_duration();
+
+            if handled.len() != indexes.len() {
+                return Err(E::fatal(format!(
+                    "Batch handler returned {} results for {} records",
+                    handled.len(),
+                    indexes.len()
+                )));
+            }
+            // a message fails with the first error of its records
+            for (index, res) in indexes.into_iter().zip(handled) {
+                if results[index].is_ok() {
+                    results[index] = res;
+                }
+            }
+        }
+
+        self.settle(messages, results).await
+    }
+
+    /// Given a result for each message, gives up on those which keep failing
+    /// and deletes those which are done.
+    ///
+    /// Returns the first unrecoverable error, if any.
+    async fn settle(
+        &self,
+        messages: &[Message],
+        results: Vec<Result<(), E>>,
+    ) -> Result<(), E> {
+        let mut done = Vec::with_capacity(messages.len());
+        let mut fatal = None;
+        for (message, res) in messages.iter().zip(results) {
+            match res {
+                Ok(_) => {
+                    metrics::MESSAGES_SUCCEEDED.inc();
+                    done.extend(message.receipt_handle.clone());
+                }
+                Err(e) if e.is_recoverable() => {
+                    log::error!("Cannot process message: {}", e);
+                    metrics::record_failure(e.code().as_deref());
+                    if should_give_up(self.conf, message) {
+                        // the rest of the batch is still deleted, this message
+                        // stays in the queue
+                        match self.quarantine(message, &e).await {
+                            Ok(_) => {
+                                done.extend(message.receipt_handle.clone())
+                            }
+                            Err(e) => {
+                                log::error!("Cannot quarantine message: {}", e);
+                                fatal.get_or_insert(e);
+                            }
+                        }
+                    } else if let Some(retry_after) = e.retry_after() {
+                        self.postpone(message, retry_after).await;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Fatal error: {}", e);
+                    metrics::record_failure(e.code().as_deref());
+                    fatal.get_or_insert(e);
+                }
+            }
+        }
+
+        if !done.is_empty() {
+            log::trace!("Deleting messages {:?}", done);
+            let not_deleted = self
+                .sqs
+                .delete_batch(self.queue_url.to_string(), done)
+                .await?;
+            if !not_deleted.is_empty() {
+                // they will be received and handled again
+                log::warn!("{} messages weren't deleted", not_deleted.len());
+            }
+        }
+
+        match fatal {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// 1. Decodes the message into information about the new S3 objects.
+    ///
+    /// 2. Lets the handler do its job with each object while keeping the
+    ///    message invisible to other consumers. Stops at the first error.
+    ///
+    /// The message is deleted by the caller to mark the task as "done".
+    async fn process<H: Handler<Error = E>>(
+        &self,
+        handler: &H,
+        message: &Message,
+    ) -> Result<(), E> {
+        // 1.
+        let (receipt_handle, records) = decode::<E>(message)?;
+
+        // 2.
+        let _timer = metrics::HANDLER_DURATION.start_timer();
+        let handled = async {
+            for record in records {
+                let span = telemetry::span(&record.key);
+                handler.handle(record).instrument(span).await?;
+            }
+
+            Ok(())
+        };
+        tokio::select! {
+            biased;
+            res = handled => res,
+            _ = heartbeat(
+                self.sqs,
+                self.queue_url,
+                receipt_handle,
+                self.visibility_timeout
+            ) => {
+                unreachable!("Heartbeat never finishes")
+            }
+        }
+    }
+
+    /// Moves a message which keeps failing out of the way. It's sent to the
+    /// dead-letter queue and recorded in the failed jobs table, if those are
+    /// configured. The caller then deletes it from the input queue.
+    async fn quarantine(&self, message: &Message, error: &E) -> Result<(), E> {
+        let body = message.body.clone().unwrap_or_default();
+        let s3_key = NewS3Object::from_message(&body)
+            .ok()
+            .and_then(|records| records.into_iter().next())
+            .map(|r| r.key);
+        let receive_count = sqs::receive_count(message).unwrap_or_default();
+        log::warn!(
+            "Giving up on message {:?} for object {:?} after {} receives",
+            message.message_id,
+            s3_key,
+            receive_count
+        );
+
+        if let Some(dead_letter_queue_url) = &self.conf.dead_letter_queue_url {
+            self.sqs
+                .send(dead_letter_queue_url.clone(), body.clone())
+                .await?;
+        }
+
+        if let Some(failed_jobs) = self.failed_jobs {
+            failed_jobs
+                .insert(
+                    self.queue_url,
+                    s3_key.as_deref(),
+                    &body,
+                    &error.to_string(),
+                    error.code().as_deref(),
+                    receive_count,
+                )
+                .map_err(E::fatal)?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the message visible again after given duration, so that it's
+    /// retried no sooner nor much later than the error asks for.
+    async fn postpone(&self, message: &Message, retry_after: Duration) {
+        let receipt_handle = match &message.receipt_handle {
+            Some(receipt_handle) => receipt_handle.clone(),
+            None => return,
+        };
+
+        log::debug!(
+            "Retrying message {:?} in {:?}",
+            receipt_handle,
+            retry_after
+        );
+        let res = self
+            .sqs
+            .change_visibility(
+                self.queue_url.to_string(),
+                receipt_handle,
+                retry_after,
+            )
+            .await;
+        if let Err(e) = res {
+            // the message is retried after its visibility timeout instead
+            log::warn!("Cannot postpone message: {:?}", e);
+        }
+    }
+}
+
+/// Extracts the receipt handle and the body from the message, and decodes the
+/// body into information about the new S3 objects.
+fn decode<E: HandlerError>(
+    message: &Message,
+) -> Result<(&str, Vec<NewS3Object>), E> {
+    let message_id = &message.message_id;
+    let receipt_handle = message
+        .receipt_handle
+        .as_deref()
+        .ok_or_else(|| E::new("Each message must have a receipt handle"))?;
+    let body = message.body.as_deref().ok_or_else(|| {
+        E::new(format!(
+            "Received message {:?} with an empty body",
+            message_id
+        ))
+    })?;
+
+    log::trace!("Received a new message with body: \n\n{}", body);
+    let records = NewS3Object::from_message(body)?;
+
+    Ok((receipt_handle, records))
+}
+
+fn should_give_up(conf: &Conf, message: &Message) -> bool {
+    conf.max_receive_count
+        .zip(sqs::receive_count(message))
+        .map(|(max_receive_count, receive_count)| {
+            receive_count >= max_receive_count
+        })
+        .unwrap_or(false)
+}
+
+/// Extends the visibility of an in-flight message in regular intervals. It never
+/// finishes, it's cancelled by being dropped when the handler is done.
+async fn heartbeat(
+    sqs: &dyn SqsExt,
+    queue_url: &str,
+    receipt_handle: &str,
+    visibility_timeout: Duration,
+) {
+    if visibility_timeout == Duration::from_secs(0) {
+        // the message is visible right away, nothing to extend
+        return futures::future::pending().await;
+    }
+
+    // we extend the visibility well before it runs out to account for latency
+    let period = visibility_timeout / 2;
+    loop {
+        time::sleep(period).await;
+
+        log::debug!(
+            "Extending visibility of message (handle {:?}) by {:?}",
+            receipt_handle,
+            visibility_timeout
+        );
+        let res = sqs
+            .change_visibility(
+                queue_url.to_string(),
+                receipt_handle.to_string(),
+                visibility_timeout,
+            )
+            .await;
+        if let Err(e) = res {
+            // the handler might still finish in time, therefore we don't stop
+            log::warn!("Cannot extend visibility of message: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failed_jobs;
+    use rusoto_sqs::{ChangeMessageVisibilityError, DeleteMessageError};
+    use serde_json::json;
+    use std::{cell::RefCell, collections::HashMap, fmt, sync::Mutex};
+
+    const VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[tokio::test]
+    async fn it_handles_record_and_deletes_message() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub::default();
+
+        worker(&sqs, &Conf::default(), None)
+            .process_batch(&handler, &[new_message("key", "handle")])
+            .await
+            .unwrap();
+
+        assert_eq!(handler.handled.borrow().as_slice(), &["key".to_string()]);
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[("queue_url".to_string(), vec!["handle".to_string()])]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_handles_batch_concurrently_and_deletes_it_at_once() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            sleep: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+
+        let started_at = time::Instant::now();
+        worker(&sqs, &Conf::default(), None)
+            .process_batch(
+                &handler,
+                &[
+                    new_message("key1", "handle1"),
+                    new_message("key2", "handle2"),
+                    new_message("key3", "handle3"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_millis(400));
+        assert_eq!(handler.handled.borrow().len(), 3);
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[(
+                "queue_url".to_string(),
+                vec![
+                    "handle1".to_string(),
+                    "handle2".to_string(),
+                    "handle3".to_string()
+                ]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_handles_records_together() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            fail_with: Some(TestError {
+                is_recoverable: true,
+            }),
+            fail_for_key: Some("key3".to_string()),
+            ..Default::default()
+        };
+        let malformed = Message {
+            body: Some("{}".to_string()),
+            receipt_handle: Some("handle2".to_string()),
+            ..Default::default()
+        };
+
+        worker(&sqs, &Conf::default(), None)
+            .process_together(
+                &handler,
+                &[
+                    new_message("key1", "handle1"),
+                    malformed,
+                    new_message("key3", "handle3"),
+                    new_message("key4", "handle4"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(*handler.batches.borrow(), 1);
+        assert_eq!(
+            handler.handled.borrow().as_slice(),
+            &["key1".to_string(), "key4".to_string()]
+        );
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[(
+                "queue_url".to_string(),
+                vec!["handle1".to_string(), "handle4".to_string()]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_handles_every_record_of_message() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub::default();
+        let record = |key: &str| {
+            json!({
+                "awsRegion": "eu-west-1",
+                "s3": {
+                    "bucket": { "name": "bucket" },
+                    "object": { "key": key },
+                },
+            })
+        };
+        let message = Message {
+            body: Some(
+                json!({
+                    "Type": "Notification",
+                    "Message": json!({
+                        "Records": [record("key+1"), record("key2")],
+                    })
+                    .to_string(),
+                })
+                .to_string(),
+            ),
+            receipt_handle: Some("handle".to_string()),
+            ..Default::default()
+        };
+        let test_event = Message {
+            body: Some(json!({ "Event": "s3:TestEvent" }).to_string()),
+            receipt_handle: Some("test_handle".to_string()),
+            ..Default::default()
+        };
+
+        worker(&sqs, &Conf::default(), None)
+            .process_batch(&handler, &[message, test_event])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handler.handled.borrow().as_slice(),
+            &["key 1".to_string(), "key2".to_string()]
+        );
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[(
+                "queue_url".to_string(),
+                vec!["handle".to_string(), "test_handle".to_string()]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_deletes_large_batch_at_once() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub::default();
+        let messages: Vec<_> = (0..25)
+            .map(|i| new_message(&format!("key{}", i), &format!("handle{}", i)))
+            .collect();
+
+        worker(&sqs, &Conf::default(), None)
+            .process_together(&handler, &messages)
+            .await
+            .unwrap();
+
+        assert_eq!(handler.handled.borrow().len(), 25);
+        // the sqs client splits it into requests which SQS accepts
+        let deleted = sqs.deleted.lock().unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].1.len(), 25);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_delete_message_if_handler_fails() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            fail_with: Some(TestError {
+                is_recoverable: true,
+            }),
+            ..Default::default()
+        };
+
+        worker(&sqs, &Conf::default(), None)
+            .process_batch(&handler, &[new_message("key", "handle")])
+            .await
+            .unwrap();
+
+        assert!(sqs.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_returns_fatal_error_after_batch_is_done() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            fail_with: Some(TestError {
+                is_recoverable: false,
+            }),
+            fail_for_key: Some("key2".to_string()),
+            ..Default::default()
+        };
+
+        let e = worker(&sqs, &Conf::default(), None)
+            .process_batch(
+                &handler,
+                &[
+                    new_message("key1", "handle1"),
+                    new_message("key2", "handle2"),
+                ],
+            )
+            .await
+            .unwrap_err();
+
+        assert!(!e.is_recoverable());
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[("queue_url".to_string(), vec!["handle1".to_string()])]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_returns_recoverable_error_on_malformed_message() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub::default();
+        let worker = worker(&sqs, &Conf::default(), None);
+
+        let message = Message {
+            body: Some("{}".to_string()),
+            receipt_handle: Some("handle".to_string()),
+            ..Default::default()
+        };
+        let e = worker.process(&handler, &message).await.unwrap_err();
+        assert!(e.is_recoverable());
+
+        let message = Message {
+            receipt_handle: Some("handle".to_string()),
+            ..Default::default()
+        };
+        let e = worker.process(&handler, &message).await.unwrap_err();
+        assert!(e.is_recoverable());
+
+        assert!(handler.handled.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_extends_visibility_while_handler_runs() {
+        time::pause();
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            sleep: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let conf = Conf::default();
+        let worker = Worker {
+            visibility_timeout: Duration::from_millis(400),
+            ..worker(&sqs, &conf, None)
+        };
+
+        worker
+            .process(&handler, &new_message("key", "handle"))
+            .await
+            .unwrap();
+
+        // extended after 200ms and 400ms, then the handler finished
+        assert_eq!(*sqs.visibility_changes.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn it_caps_batch_size() {
+        let conf = |concurrency| Conf {
+            concurrency,
+            ..Default::default()
+        };
+
+        assert_eq!(conf(None).batch_size(), 1);
+        assert_eq!(conf(Some(0)).batch_size(), 1);
+        assert_eq!(conf(Some(4)).batch_size(), 4);
+        assert_eq!(conf(Some(100)).batch_size(), sqs::MAX_BATCH_SIZE);
+    }
+
+    #[test]
+    fn it_gives_up_only_after_max_receive_count() {
+        let mut message = new_message("key", "handle");
+        let conf = Conf {
+            max_receive_count: Some(3),
+            ..Default::default()
+        };
+
+        // no receive count attribute
+        assert!(!should_give_up(&conf, &message));
+
+        set_receive_count(&mut message, 2);
+        assert!(!should_give_up(&conf, &message));
+        assert!(!should_give_up(&Conf::default(), &message));
+
+        set_receive_count(&mut message, 3);
+        assert!(should_give_up(&conf, &message));
+        assert!(!should_give_up(&Conf::default(), &message));
+    }
+
+    #[tokio::test]
+    async fn it_quarantines_message() {
+        let sqs = SqsStub::default();
+        let handler = HandlerStub {
+            fail_with: Some(TestError {
+                is_recoverable: true,
+            }),
+            ..Default::default()
+        };
+        let failed_jobs = failed_jobs::tests::open_in_memory();
+        let conf = Conf {
+            max_receive_count: Some(3),
+            dead_letter_queue_url: Some("dlq_url".to_string()),
+            ..Default::default()
+        };
+        let mut message = new_message("key", "handle");
+        set_receive_count(&mut message, 3);
+
+        worker(&sqs, &conf, Some(&failed_jobs))
+            .process_batch(&handler, &[message.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sqs.sent.lock().unwrap().as_slice(),
+            &[("dlq_url".to_string(), message.body.clone().unwrap())]
+        );
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[("queue_url".to_string(), vec!["handle".to_string()])]
+        );
+
+        let jobs = failed_jobs.list().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].queue_url, "queue_url");
+        assert_eq!(jobs[0].s3_key, Some("key".to_string()));
+        assert_eq!(jobs[0].message_body, message.body.unwrap());
+        assert_eq!(jobs[0].error, handler.fail_with.unwrap().to_string());
+        assert_eq!(jobs[0].receive_count, 3);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_rest_of_batch_if_quarantine_fails() {
+        let sqs = SqsStub {
+            fail_send: true,
+            ..Default::default()
+        };
+        let handler = HandlerStub {
+            fail_with: Some(TestError {
+                is_recoverable: true,
+            }),
+            fail_for_key: Some("key2".to_string()),
+            ..Default::default()
+        };
+        let conf = Conf {
+            max_receive_count: Some(3),
+            dead_letter_queue_url: Some("dlq_url".to_string()),
+            ..Default::default()
+        };
+        let mut message = new_message("key2", "handle2");
+        set_receive_count(&mut message, 3);
+
+        let e = worker(&sqs, &conf, None)
+            .process_batch(
+                &handler,
+                &[
+                    new_message("key1", "handle1"),
+                    message,
+                    new_message("key3", "handle3"),
+                ],
+            )
+            .await
+            .unwrap_err();
+
+        assert!(!e.is_recoverable());
+        assert_eq!(
+            sqs.deleted.lock().unwrap().as_slice(),
+            &[(
+                "queue_url".to_string(),
+                vec!["handle1".to_string(), "handle3".to_string()]
+            )]
+        );
+    }
+
+    fn worker<'a>(
+        sqs: &'a SqsStub,
+        conf: &'a Conf,
+        failed_jobs: Option<&'a FailedJobs>,
+    ) -> Worker<'a, TestError> {
+        Worker {
+            sqs,
+            queue_url: "queue_url",
+            visibility_timeout: VISIBILITY_TIMEOUT,
+            conf,
+            failed_jobs,
+            _error: PhantomData,
+        }
+    }
+
+    fn set_receive_count(message: &mut Message, receive_count: i64) {
+        message.attributes.get_or_insert_with(HashMap::new).insert(
+            sqs::APPROXIMATE_RECEIVE_COUNT.to_string(),
+            receive_count.to_string(),
+        );
+    }
+
+    fn new_message(key: &str, receipt_handle: &str) -> Message {
+        Message {
+            // https://docs.aws.amazon.com/AmazonS3/latest/userguide/notification-content-structure.html
+            body: Some(
+                serde_json::to_string(&serde_json::json!({
+                    "Records": [
+                       {
+                          "awsRegion": "eu-west-1",
+                          "s3": {
+                             "bucket": {
+                                "name": "bucket",
+                             },
+                             "object": {
+                                "key": key,
+                             }
+                          }
+                       }
+                    ]
+                }))
+                .unwrap(),
+            ),
+            receipt_handle: Some(receipt_handle.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestError {
+        is_recoverable: bool,
+    }
+
+    impl Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl HandlerError for TestError {
+        fn new(_: impl Display) -> Self {
+            Self {
+                is_recoverable: true,
+            }
+        }
+
+        fn fatal(_: impl Display) -> Self {
+            Self {
+                is_recoverable: false,
+            }
+        }
+
+        fn is_recoverable(&self) -> bool {
+            self.is_recoverable
+        }
+    }
+
+    impl From<serde_json::Error> for TestError {
+        fn from(e: serde_json::Error) -> Self {
+            Self::new(e)
+        }
+    }
+
+    impl From<io::Error> for TestError {
+        fn from(_: io::Error) -> Self {
+            Self {
+                is_recoverable: false,
+            }
+        }
+    }
+
+    impl<E> From<RusotoError<E>> for TestError {
+        fn from(_: RusotoError<E>) -> Self {
+            Self {
+                is_recoverable: false,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct HandlerStub {
+        handled: RefCell<Vec<String>>,
+        fail_with: Option<TestError>,
+        /// If set, fails only for this key.
+        fail_for_key: Option<String>,
+        sleep: Option<Duration>,
+        /// How many times was the batch handler called.
+        batches: RefCell<usize>,
+    }
+
+    #[async_trait(?Send)]
+    impl Handler for HandlerStub {
+        type Error = TestError;
+
+        async fn handle(&self, record: NewS3Object) -> Result<(), TestError> {
+            if let Some(duration) = self.sleep {
+                time::sleep(duration).await;
+            }
+
+            if let Some(e) = self.fail_with.clone() {
+                let key = Some(&record.key);
+                if self.fail_for_key.is_none()
+                    || self.fail_for_key.as_ref() == key
+                {
+                    return Err(e);
+                }
+            }
+
+            self.handled.borrow_mut().push(record.key);
+            Ok(())
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl BatchHandler for HandlerStub {
+        type Error = TestError;
+
+        async fn handle_batch(
+            &self,
+            records: Vec<NewS3Object>,
+        ) -> Vec<Result<(), TestError>> {
+            *self.batches.borrow_mut() += 1;
+
+            let mut results = vec![];
+            for record in records {
+                results.push(self.handle(record).await);
+            }
+            results
+        }
+    }
+
+    #[derive(Default)]
+    struct SqsStub {
+        deleted: Mutex<Vec<(String, Vec<String>)>>,
+        sent: Mutex<Vec<(String, String)>>,
+        visibility_changes: Mutex<usize>,
+        /// If set, sending a message fails.
+        fail_send: bool,
+    }
+
+    #[async_trait]
+    impl SqsExt for SqsStub {
+        async fn receive_batch(
+            &self,
+            _: String,
+            _: usize,
+        ) -> Result<Vec<Message>, RusotoError<ReceiveMessageError>> {
+            unimplemented!()
+        }
+
+        async fn delete(
+            &self,
+            _: String,
+            _: String,
+        ) -> Result<(), RusotoError<DeleteMessageError>> {
+            unimplemented!()
+        }
+
+        async fn delete_batch(
+            &self,
+            queue_url: String,
+            receipt_handles: Vec<String>,
+        ) -> Result<Vec<String>, RusotoError<DeleteMessageBatchError>> {
+            self.deleted
+                .lock()
+                .unwrap()
+                .push((queue_url, receipt_handles));
+            Ok(vec![])
+        }
+
+        async fn send(
+            &self,
+            queue_url: String,
+            body: String,
+        ) -> Result<(), RusotoError<SendMessageError>> {
+            if self.fail_send {
+                return Err(RusotoError::Blocking);
+            }
+
+            self.sent.lock().unwrap().push((queue_url, body));
+            Ok(())
+        }
+
+        async fn get_attributes(
+            &self,
+            _queue_url: String,
+            _attrs: Vec<String>,
+        ) -> Result<HashMap<String, String>, RusotoError<GetQueueAttributesError>>
+        {
+            unimplemented!()
+        }
+
+        async fn change_visibility(
+            &self,
+            _queue_url: String,
+            _receipt_handle: String,
+            _timeout: Duration,
+        ) -> Result<(), RusotoError<ChangeMessageVisibilityError>> {
+            *self.visibility_changes.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! A learned alternative to the weighted averages in [`crate::select`]. Each
+//! phrase and each word is described by [`Features`]: the estimate of each
+//! source plus properties of its text, such as its position in the newsletter
+//! or how much of it is uppercase. A logistic regression then turns the
+//! features into the probability that the phrase is a deal or that the word is
+//! a voucher.
+//!
+//! The features of each selected offer are stored with it. Once offers are
+//! labelled as right or wrong in the `label` column, `newsletter-cli train`
+//! fits a [`Model`] on them and writes it to a file which the sieve loads on
+//! startup, see [`Conf::model_path`].
+//!
+//! Features are keyed by name, so a model keeps working when sources are added
+//! or removed. Unknown features are ignored and missing ones are zero.
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use shared::document::Phrase;
+use std::{collections::BTreeMap, fs};
+
+pub type Features = BTreeMap<String, f64>;
+
+/// How many passes over the examples the training makes.
+const EPOCHS: usize = 2_000;
+
+const LEARNING_RATE: f64 = 0.5;
+
+/// Keeps weights of rare features from growing out of proportion.
+const L2_PENALTY: f64 = 0.001;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Model {
+    pub deal: Logistic,
+    pub voucher: Logistic,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Logistic {
+    pub bias: f64,
+    pub weights: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Deal,
+    Voucher,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    pub kind: Kind,
+    pub features: Features,
+    /// Whether the offer was right.
+    pub label: bool,
+}
+
+impl Model {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            Error::fatal(format!("Cannot read model file {}: {}", path, e))
+                .with_code("model")
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| {
+            Error::fatal(format!("Invalid model file {}: {}", path, e))
+                .with_code("model")
+        })
+    }
+
+    /// Fits a regression for deals and one for vouchers. Both kinds must have
+    /// examples.
+    pub fn train(examples: &[Example]) -> Result<Self, Error> {
+        let of_kind = |kind| -> Result<Vec<&Example>, Error> {
+            let examples: Vec<_> =
+                examples.iter().filter(|e| e.kind == kind).collect();
+            if examples.is_empty() {
+                Err(Error::new(format!("No labelled {:?} examples", kind))
+                    .with_code("no_examples"))
+            } else {
+                Ok(examples)
+            }
+        };
+
+        Ok(Self {
+            deal: Logistic::train(&of_kind(Kind::Deal)?),
+            voucher: Logistic::train(&of_kind(Kind::Voucher)?),
+        })
+    }
+}
+
+impl Logistic {
+    /// Probability between 0 and 1.
+    pub fn predict(&self, features: &Features) -> f64 {
+        sigmoid(self.linear(features))
+    }
+
+    /// Batch gradient descent on the log loss.
+    fn train(examples: &[&Example]) -> Self {
+        let mut model = Self::default();
+        for name in examples.iter().flat_map(|e| e.features.keys()) {
+            model.weights.insert(name.clone(), 0.0);
+        }
+
+        let n = examples.len() as f64;
+        for _ in 0..EPOCHS {
+            let mut bias_gradient = 0.0;
+            let mut gradients: BTreeMap<&str, f64> = BTreeMap::new();
+            for example in examples {
+                let error = model.predict(&example.features)
+                    - if example.label { 1.0 } else { 0.0 };
+                bias_gradient += error;
+                for (name, value) in &example.features {
+                    *gradients.entry(name).or_default() += error * value;
+                }
+            }
+
+            model.bias -= LEARNING_RATE * bias_gradient / n;
+            for (name, weight) in model.weights.iter_mut() {
+                let gradient = gradients.get(name.as_str()).unwrap_or(&0.0) / n
+                    + L2_PENALTY * *weight;
+                *weight -= LEARNING_RATE * gradient;
+            }
+        }
+
+        model
+    }
+
+    fn linear(&self, features: &Features) -> f64 {
+        features
+            .iter()
+            .filter_map(|(name, value)| {
+                self.weights.get(name).map(|weight| weight * value)
+            })
+            .sum::<f64>()
+            + self.bias
+    }
+}
+
+/// Features of the phrase at given index as a deal.
+pub fn phrase_features(phrases: &[Phrase], phrase_index: usize) -> Features {
+    let p = &phrases[phrase_index];
+    let mut features = Features::new();
+
+    for (source, estimate) in &p.estimates {
+        features.insert(format!("phrase.{}", source.as_str()), *estimate);
+    }
+    for w in &p.words {
+        for (source, estimate) in &w.estimates {
+            let max = features
+                .entry(format!("word_max.{}", source.as_str()))
+                .or_insert(0.0);
+            *max = max.max(*estimate);
+        }
+    }
+
+    features.insert("position".to_string(), position(phrases, phrase_index));
+    features.insert(
+        "word_count_ln".to_string(),
+        (p.words.len() as f64 + 1.0).ln(),
+    );
+    features.insert("confidence".to_string(), p.confidence_weight());
+    insert_text_features(&mut features, &p.text);
+
+    features
+}
+
+/// Features of the word at given indexes as a voucher.
+pub fn word_features(
+    phrases: &[Phrase],
+    phrase_index: usize,
+    word_index: usize,
+) -> Features {
+    let p = &phrases[phrase_index];
+    let w = &p.words[word_index];
+    let mut features = Features::new();
+
+    for (source, estimate) in &p.estimates {
+        features.insert(format!("phrase.{}", source.as_str()), *estimate);
+    }
+    for (source, estimate) in &w.estimates {
+        features.insert(format!("word.{}", source.as_str()), *estimate);
+    }
+
+    features.insert("position".to_string(), position(phrases, phrase_index));
+    features.insert(
+        "len_ln".to_string(),
+        (w.text.chars().count() as f64 + 1.0).ln(),
+    );
+    features.insert("confidence".to_string(), w.confidence_weight());
+    features.insert(
+        "phrase_has_percent".to_string(),
+        indicator(p.text.contains('%')),
+    );
+    insert_text_features(&mut features, &w.text);
+
+    features
+}
+
+/// Between 0 for the first phrase and 1 for the last one.
+fn position(phrases: &[Phrase], phrase_index: usize) -> f64 {
+    phrase_index as f64 / (phrases.len().max(2) - 1) as f64
+}
+
+fn insert_text_features(features: &mut Features, text: &str) {
+    let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+    let uppercase = text.chars().filter(|c| c.is_uppercase()).count();
+    let uppercase_ratio = if letters == 0 {
+        0.0
+    } else {
+        uppercase as f64 / letters as f64
+    };
+
+    features.insert("uppercase_ratio".to_string(), uppercase_ratio);
+    features.insert(
+        "has_digit".to_string(),
+        indicator(text.chars().any(|c| c.is_ascii_digit())),
+    );
+    features.insert("has_percent".to_string(), indicator(text.contains('%')));
+}
+
+fn indicator(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::document::Source;
+
+    #[test]
+    fn it_learns_to_separate_examples() {
+        let example = |kind, voucherc: f64, label| Example {
+            kind,
+            features: vec![
+                ("word.voucherc".to_string(), voucherc),
+                ("has_digit".to_string(), 1.0),
+            ]
+            .into_iter()
+            .collect(),
+            label,
+        };
+        let examples = vec![
+            example(Kind::Voucher, 0.9, true),
+            example(Kind::Voucher, 0.8, true),
+            example(Kind::Voucher, 0.3, false),
+            example(Kind::Voucher, 0.1, false),
+            example(Kind::Deal, 0.5, true),
+        ];
+
+        let model = Model::train(&examples).unwrap();
+
+        let high = model.voucher.predict(&examples[0].features);
+        let low = model.voucher.predict(&examples[3].features);
+        assert!(high > 0.5, "{} should be above .5", high);
+        assert!(low < 0.5, "{} should be below .5", low);
+
+        // unknown features don't count
+        let mut features = examples[0].features.clone();
+        features.insert("word.crystal_ball".to_string(), 1.0);
+        assert_eq!(model.voucher.predict(&features), high);
+
+        assert!(Model::train(&examples[..4]).is_err());
+    }
+
+    #[test]
+    fn it_extracts_features() {
+        let mut phrases =
+            vec![Phrase::new("Use CODE20"), Phrase::new("50% off")];
+        phrases[0].estimates.insert(Source::Dealc, 0.7);
+        phrases[0].words[1].estimates.insert(Source::Voucherc, 0.9);
+
+        let features = word_features(&phrases, 0, 1);
+        assert_eq!(features["phrase.dealc"], 0.7);
+        assert_eq!(features["word.voucherc"], 0.9);
+        assert_eq!(features["position"], 0.0);
+        assert_eq!(features["uppercase_ratio"], 1.0);
+        assert_eq!(features["has_digit"], 1.0);
+        assert_eq!(features["has_percent"], 0.0);
+
+        let features = phrase_features(&phrases, 1);
+        assert_eq!(features["position"], 1.0);
+        assert_eq!(features["has_percent"], 1.0);
+        assert!(!features.contains_key("phrase.dealc"));
+
+        let features = phrase_features(&phrases, 0);
+        assert_eq!(features["word_max.voucherc"], 0.9);
+    }
+}
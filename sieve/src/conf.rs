@@ -22,4 +22,17 @@ pub struct Conf {
     pub ocr_bucket_name: String,
     /// Path to the sqlite3 file into which we store results.
     pub database_path: String,
+    /// Path to the JSON file with weights and thresholds of the selection, see
+    /// [`crate::tuning`].
+    ///
+    /// # Default
+    /// If not set, the built-in tuning of version "default" is used.
+    pub tuning_path: Option<String>,
+    /// Path to the JSON file written by `newsletter-cli train`, see
+    /// [`crate::combiner`].
+    ///
+    /// # Default
+    /// If not set, offers are estimated by the weighted averages of the
+    /// tuning.
+    pub model_path: Option<String>,
 }
@@ -1,10 +1,18 @@
+use crate::combiner::{Example, Kind};
 use crate::prelude::*;
 use crate::select::{Deal, Voucher};
+use shared::metrics;
 use sqlite::Connection;
+use std::fs;
 
+/// Stores the offers and marks the newsletter as processed in one
+/// transaction. An offer which is already stored for the newsletter, e.g.
+/// because the message was redelivered, keeps its id, state and label, and
+/// only gets the link, tuning version and features updated.
 pub fn insert(
     conn: &Connection,
     newsletter_id: &str,
+    tuning_version: &str,
     deals: Vec<Deal>,
     vouchers: Vec<Voucher>,
 ) -> Result<(), Error> {
@@ -14,16 +22,48 @@ pub fn insert(
     }
 
     log::info!(
-        "Inserting {} deals and {} vouchers for {}",
+        "Upserting {} deals and {} vouchers for {}",
         deals.len(),
         vouchers.len(),
         newsletter_id
     );
 
+    let _timer = metrics::STEP_DURATION
+        .with_label_values(&["sqlite_insert"])
+        .start_timer();
+    transaction(conn, || {
+        upsert_offers(conn, newsletter_id, tuning_version, &deals, &vouchers)?;
+
+        // this is a signal to syncing logic that the deals and vouchers
+        // associated with this email can be send to customers APIs
+        let mut statement = conn.prepare(
+            "UPDATE inbound_emails SET state = 'processed' WHERE s3_key = ?",
+        )?;
+        statement.bind(1, newsletter_id)?;
+        while !matches!(statement.next()?, sqlite::State::Done) {
+            //
+        }
+
+        Ok(())
+    })
+}
+
+fn upsert_offers(
+    conn: &Connection,
+    newsletter_id: &str,
+    tuning_version: &str,
+    deals: &[Deal],
+    vouchers: &[Voucher],
+) -> Result<(), Error> {
     let sql = format!(
-        "INSERT INTO offers (s3_key, deal, voucher, link) VALUES {}",
+        "INSERT INTO offers \
+        (s3_key, deal, voucher, link, tuning_version, features) VALUES {} \
+        ON CONFLICT (s3_key, deal, IFNULL(voucher, '')) DO UPDATE SET \
+        link = excluded.link, \
+        tuning_version = excluded.tuning_version, \
+        features = excluded.features",
         (0..(deals.len() + vouchers.len()))
-            .map(|_| "(?, ?, ?, ?)".to_string())
+            .map(|_| "(?, ?, ?, ?, ?, ?)".to_string())
             .collect::<Vec<_>>()
             .join(",")
     );
@@ -31,16 +71,21 @@ pub fn insert(
     let mut statement = conn.prepare(sql)?;
     let mut binding_index = 1;
 
-    for deal in &deals {
+    for deal in deals {
         statement.bind(binding_index, newsletter_id)?;
         statement.bind(binding_index + 1, deal.text.as_str())?;
         statement.bind(binding_index + 2, None::<&str>)?; // no voucher
         statement
             .bind(binding_index + 3, deal.link.as_ref().map(|s| s.as_str()))?;
-        binding_index += 4;
+        statement.bind(binding_index + 4, tuning_version)?;
+        statement.bind(
+            binding_index + 5,
+            serde_json::to_string(&deal.features)?.as_str(),
+        )?;
+        binding_index += 6;
     }
 
-    for voucher in &vouchers {
+    for voucher in vouchers {
         statement.bind(binding_index, newsletter_id)?;
         statement.bind(binding_index + 1, voucher.phrase.as_str())?;
         statement.bind(binding_index + 2, voucher.text.as_str())?;
@@ -48,23 +93,162 @@ pub fn insert(
             binding_index + 3,
             voucher.link.as_ref().map(|s| s.as_str()),
         )?;
-        binding_index += 4;
+        statement.bind(binding_index + 4, tuning_version)?;
+        statement.bind(
+            binding_index + 5,
+            serde_json::to_string(&voucher.features)?.as_str(),
+        )?;
+        binding_index += 6;
     }
 
     while !matches!(statement.next()?, sqlite::State::Done) {
         //
     }
 
-    // this is a signal to syncing logic that the deals and vouchers associated
-    // with this email can be send to customers APIs
-    conn.execute(format!(
-        "UPDATE inbound_emails SET state = 'processed' WHERE s3_key = '{}'",
-        newsletter_id
-    ))?;
+    Ok(())
+}
+
+/// Commits if the closure succeeds and rolls back otherwise.
+fn transaction<T>(
+    conn: &Connection,
+    f: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    conn.execute("BEGIN IMMEDIATE;")?;
+    let res = f().and_then(|t| {
+        conn.execute("COMMIT;")?;
+        Ok(t)
+    });
+    if res.is_err() {
+        // errors if the transaction was committed after all, nothing to do
+        conn.execute("ROLLBACK;").ok();
+    }
+
+    res
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Offer {
+    /// Stays the same when the offer is upserted again.
+    pub id: i64,
+    pub deal: String,
+    pub voucher: Option<String>,
+    pub link: Option<String>,
+    /// See [`crate::tuning::Tuning::version`].
+    pub tuning_version: Option<String>,
+}
+
+/// Returns offers of given newsletter in the order they were inserted.
+pub fn offers(
+    conn: &Connection,
+    newsletter_id: &str,
+) -> Result<Vec<Offer>, Error> {
+    let mut statement = conn.prepare(
+        "SELECT id, deal, voucher, link, tuning_version FROM offers \
+        WHERE s3_key = ? ORDER BY id",
+    )?;
+    statement.bind(1, newsletter_id)?;
+
+    let mut offers = vec![];
+    while let sqlite::State::Row = statement.next()? {
+        offers.push(Offer {
+            id: statement.read(0)?,
+            deal: statement.read(1)?,
+            voucher: statement.read(2)?,
+            link: statement.read(3)?,
+            tuning_version: statement.read(4)?,
+        });
+    }
+
+    Ok(offers)
+}
+
+/// Offers which were reviewed and which have features, to train the
+/// [`crate::combiner::Model`] on.
+pub fn labelled_examples(conn: &Connection) -> Result<Vec<Example>, Error> {
+    let mut statement = conn.prepare(
+        "SELECT voucher IS NULL, features, label FROM offers \
+        WHERE label IS NOT NULL AND features IS NOT NULL",
+    )?;
+
+    let mut examples = vec![];
+    while let sqlite::State::Row = statement.next()? {
+        let is_deal: i64 = statement.read(0)?;
+        let features: String = statement.read(1)?;
+        let label: i64 = statement.read(2)?;
+        examples.push(Example {
+            kind: if is_deal == 1 {
+                Kind::Deal
+            } else {
+                Kind::Voucher
+            },
+            features: serde_json::from_str(&features)?,
+            label: label == 1,
+        });
+    }
+
+    Ok(examples)
+}
+
+/// Fails if the connection is query only or if the database file is read
+/// only. It's cheap enough to run on every readiness probe, as it neither
+/// reads the tables nor takes any lock on the database.
+pub fn check_writable(conn: &Connection) -> Result<(), Error> {
+    let mut query_only = false;
+    conn.iterate("PRAGMA query_only", |row| {
+        query_only = row[0].1 == Some("1");
+        true
+    })?;
+    if query_only {
+        return Err(Error::new("Database connection is query only"));
+    }
+
+    // in memory databases have no file
+    let mut path = None;
+    conn.iterate("PRAGMA database_list", |row| {
+        if row[1].1 == Some("main") {
+            path = row[2].1.filter(|file| !file.is_empty()).map(String::from);
+        }
+        true
+    })?;
+    if let Some(path) = path {
+        if fs::metadata(&path)?.permissions().readonly() {
+            return Err(Error::new(format!("Database {} is read only", path)));
+        }
+    }
 
     Ok(())
 }
 
+/// Creates a database with the tables the sieve writes into, which lives only
+/// as long as the connection.
+pub fn open_in_memory() -> Result<Connection, Error> {
+    let conn = Connection::open(":memory:")?;
+    conn.execute(MIGRATION_01)?;
+    conn.execute(MIGRATION_02)?;
+    conn.execute(MIGRATION_06)?;
+    conn.execute(MIGRATION_07)?;
+    conn.execute(MIGRATION_08)?;
+
+    Ok(conn)
+}
+
+const MIGRATION_01: &str =
+    include_str!("../../migrations/000001_create_inbound_emails_table.up.sql");
+
+const MIGRATION_02: &str =
+    include_str!("../../migrations/000002_create_offers_table.up.sql");
+
+const MIGRATION_06: &str =
+    include_str!("../../migrations/000006_add_tuning_version_to_offers.up.sql");
+
+const MIGRATION_07: &str = include_str!(
+    "../../migrations/000007_add_features_and_label_to_offers.up.sql"
+);
+
+const MIGRATION_08: &str = include_str!(
+    "../../migrations/000008_add_id_and_uniqueness_to_offers.up.sql"
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,10 +276,10 @@ mod tests {
         ];
         vouchers[1].link = Some("hello".to_string());
 
-        let conn = open_in_memory_conn();
+        let conn = open_in_memory().unwrap();
         conn.execute(format!("INSERT INTO inbound_emails(s3_key, recipient_address, sender_address, received_at) VALUES ('{}', 'none', 'none', 1)", newsletter_id)).unwrap();
 
-        insert(&conn, newsletter_id, deals, vouchers)
+        insert(&conn, newsletter_id, "v1", deals, vouchers)
             .expect("Cannot insert offers");
 
         let mut rows = vec![];
@@ -135,6 +319,11 @@ mod tests {
             assert_eq!(columns[5].0, "created_at");
             assert!(columns[5].1.unwrap().parse::<i64>().is_ok());
 
+            assert_eq!(columns[6], ("tuning_version", Some("v1")));
+            assert_eq!(columns[7].0, "features");
+            assert_eq!(columns[8], ("label", None));
+            assert_eq!(columns[9].0, "id");
+
             true
         })
         .unwrap();
@@ -155,23 +344,155 @@ mod tests {
             true
         })
         .unwrap();
+
+        let offers = offers(&conn, newsletter_id).unwrap();
+        assert_eq!(offers.len(), 4);
+        assert_eq!(
+            offers[3],
+            Offer {
+                id: 4,
+                deal: "voucher2".to_string(),
+                voucher: Some("voucher2code".to_string()),
+                link: Some("hello".to_string()),
+                tuning_version: Some("v1".to_string()),
+            }
+        );
     }
 
-    const MIGRATION_01: &str = include_str!(
-        "../../migrations/000001_create_inbound_emails_table.up.sql"
-    );
+    #[test]
+    fn it_reads_labelled_examples() {
+        let conn = open_in_memory().unwrap();
+        let deal = Deal::new(0, "deal", 0.9);
+        let mut voucher = Voucher::new(0, "phrase", "CODE20", 0.9);
+        voucher.features.insert("word.voucherc".to_string(), 0.9);
+        insert(&conn, "test", "v1", vec![deal], vec![voucher]).unwrap();
+        assert_eq!(labelled_examples(&conn).unwrap(), vec![]);
+
+        conn.execute("UPDATE offers SET label = 1 WHERE voucher IS NOT NULL")
+            .unwrap();
+        conn.execute("UPDATE offers SET label = 0 WHERE voucher IS NULL")
+            .unwrap();
+
+        let examples = labelled_examples(&conn).unwrap();
+        assert_eq!(examples.len(), 2);
+        assert!(examples.iter().any(|e| e.kind == Kind::Deal && !e.label));
+        assert!(examples.iter().any(|e| e.kind == Kind::Voucher
+            && e.label
+            && e.features["word.voucherc"] == 0.9));
+    }
+
+    #[test]
+    fn it_upserts_offers() {
+        let conn = open_in_memory().unwrap();
+        let deals = || {
+            vec![
+                Deal::new(0, "deal", 0.9),
+                // the same deal twice in one newsletter is stored once
+                Deal::new(1, "deal", 0.8),
+            ]
+        };
+        let vouchers = || vec![Voucher::new(0, "deal", "CODE20", 0.9)];
 
-    const MIGRATION_02: &str =
-        include_str!("../../migrations/000002_create_offers_table.up.sql");
+        insert(&conn, "test", "v1", deals(), vouchers()).unwrap();
+        conn.execute("UPDATE offers SET label = 1 WHERE voucher IS NULL")
+            .unwrap();
+        let before = offers(&conn, "test").unwrap();
+        assert_eq!(before.len(), 2);
 
-    fn open_in_memory_conn() -> Connection {
+        let mut vouchers = vouchers();
+        vouchers[0].link = Some("link".to_string());
+        insert(&conn, "test", "v2", deals(), vouchers).unwrap();
+
+        let after = offers(&conn, "test").unwrap();
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].id, before[0].id);
+        assert_eq!(after[1].id, before[1].id);
+        assert_eq!(after[1].link, Some("link".to_string()));
+        assert_eq!(after[1].tuning_version, Some("v2".to_string()));
+        conn.iterate("SELECT label FROM offers WHERE voucher IS NULL", |c| {
+            assert_eq!(c[0], ("label", Some("1")));
+            true
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn it_migrates_offers_idempotently() {
         let conn = Connection::open(":memory:").unwrap();
+        for migration in
+            &[MIGRATION_01, MIGRATION_02, MIGRATION_06, MIGRATION_07]
+        {
+            conn.execute(migration).unwrap();
+        }
+        conn.execute(
+            "INSERT INTO offers (s3_key, deal, voucher, state, label) VALUES \
+            ('test', 'deal', NULL, 'new', NULL), \
+            ('test', 'deal', NULL, 'synced', NULL), \
+            ('test', 'deal', NULL, 'new', 1), \
+            ('test', 'deal', 'CODE20', 'new', 0), \
+            ('test', 'deal', 'CODE20', 'new', 1)",
+        )
+        .unwrap();
+        let states_and_labels = || {
+            let mut rows = vec![];
+            conn.iterate("SELECT state, label FROM offers ORDER BY id", |c| {
+                rows.push((
+                    c[0].1.unwrap().to_string(),
+                    c[1].1.map(String::from),
+                ));
+                true
+            })
+            .unwrap();
+            rows
+        };
+
+        conn.execute(MIGRATION_08).unwrap();
+        let migrated = offers(&conn, "test").unwrap();
+        assert_eq!(
+            migrated.iter().map(|o| o.id).collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+        // the duplicates are merged into the first of them with the most
+        // advanced state and the latest label
+        let merged = vec![
+            ("synced".to_string(), Some("1".to_string())),
+            ("new".to_string(), Some("1".to_string())),
+        ];
+        assert_eq!(states_and_labels(), merged);
+
+        conn.execute(MIGRATION_08).unwrap();
+        assert_eq!(offers(&conn, "test").unwrap(), migrated);
+        assert_eq!(states_and_labels(), merged);
+        // and the uniqueness is enforced
+        assert!(conn
+            .execute(
+                "INSERT INTO offers (s3_key, deal) VALUES ('test', 'deal')"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn it_checks_writable() {
+        let conn = open_in_memory().unwrap();
+        check_writable(&conn).unwrap();
+
+        conn.execute("PRAGMA query_only = 1").unwrap();
+        assert!(check_writable(&conn).is_err());
+    }
+
+    #[test]
+    fn it_checks_database_file_is_writable() {
+        let path = std::env::temp_dir()
+            .join(format!("sieve_check_writable_{}.db", std::process::id()));
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(MIGRATION_01).unwrap();
+        check_writable(&conn).unwrap();
 
-        conn.execute(MIGRATION_01)
-            .expect("Cannot run first migration");
-        conn.execute(MIGRATION_02)
-            .expect("Cannot run second migration");
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        assert!(check_writable(&conn).is_err());
 
-        conn
+        fs::remove_file(path).unwrap();
     }
 }
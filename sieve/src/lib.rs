@@ -0,0 +1,143 @@
+mod anchor;
+pub mod combiner;
+pub mod conf;
+pub mod db;
+pub mod error;
+pub mod prelude;
+mod select;
+pub mod state;
+pub mod tuning;
+
+use async_trait::async_trait;
+use prelude::*;
+use shared::{
+    anchor::Anchor, s3::NewS3Object, server::Readiness, vision::Annotation,
+    worker::Handler, Document,
+};
+use state::State;
+
+#[async_trait(?Send)]
+impl Handler for State {
+    type Error = Error;
+
+    async fn handle(&self, record: NewS3Object) -> Result<(), Error> {
+        handle(self, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Readiness for State {
+    async fn check(&self) -> Result<(), Error> {
+        db::check_writable(&self.db)
+    }
+}
+
+/// 1. Loads the document with estimates from S3.
+///
+/// 2. Selects the most likely deals and vouchers and stores them into the
+///    database.
+pub async fn handle(state: &State, record: NewS3Object) -> Result<(), Error> {
+    // 1.
+    let body = state
+        .s3
+        .get(record.bucket.clone(), record.key.clone())
+        .await?
+        .ok_or_else(|| {
+            Error::new("OCR objects cannot have empty body")
+                .with_code("empty_object")
+        })?;
+    let document: Document = serde_json::from_slice(&body)?;
+
+    // 2.
+    let (mut deals, mut vouchers) = select::deals_and_vouchers(
+        document.phrases(),
+        &state.tuning,
+        state.model.as_ref(),
+    );
+
+    if deals.is_empty() && vouchers.is_empty() {
+        log::info!("There are no vouchers nor deals for {}", record.key);
+    } else {
+        let anchor_res = state
+            .s3
+            .get(state.conf.anchor_bucket_name.clone(), record.key.clone())
+            .await;
+
+        if let Ok(Some(anchors)) = anchor_res {
+            let ocr = state
+                .s3
+                .get(state.conf.ocr_bucket_name.clone(), record.key.clone())
+                .await?
+                .ok_or_else(|| {
+                    Error::new(format!("No OCR body for {}", record.key))
+                        .with_code("missing_object")
+                })?;
+
+            let ocr: Annotation = serde_json::from_slice(&ocr)?;
+            let anchors: Vec<Anchor> = serde_json::from_slice(&anchors)?;
+
+            anchor::find_hrefs_for_resources(
+                anchors,
+                ocr,
+                &mut deals,
+                &mut vouchers,
+            );
+        } else {
+            log::warn!("Anchors for {} not found", record.key);
+        }
+
+        db::insert(
+            &state.db,
+            &record.key,
+            &state.tuning.version,
+            deals,
+            vouchers,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{s3::memory::MemoryS3, S3Ext};
+
+    #[tokio::test]
+    async fn it_stores_offers_once_when_message_is_redelivered() {
+        let record = NewS3Object {
+            region: "eu-west-1".to_string(),
+            bucket: "predictions".to_string(),
+            key: "test".to_string(),
+        };
+        let document = select::tests::testing_document("default");
+        let s3 = MemoryS3::new();
+        s3.put(
+            record.bucket.clone(),
+            record.key.clone(),
+            serde_json::to_vec(&document).unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let state = State {
+            conf: Conf {
+                anchor_bucket_name: "anchors".to_string(),
+                ocr_bucket_name: "ocr".to_string(),
+                ..Default::default()
+            },
+            s3: Box::new(s3),
+            db: db::open_in_memory().unwrap(),
+            tuning: Default::default(),
+            model: None,
+        };
+
+        handle(&state, record.clone()).await.unwrap();
+        let offers = db::offers(&state.db, &record.key).unwrap();
+        assert!(!offers.is_empty());
+
+        handle(&state, record.clone()).await.unwrap();
+        assert_eq!(db::offers(&state.db, &record.key).unwrap(), offers);
+    }
+}
@@ -1,15 +1,27 @@
 mod deal;
 mod voucher;
 
+use crate::{
+    combiner::Model,
+    tuning::{RetainTuning, Tuning},
+};
 use shared::document::Phrase;
 use std::cmp::Ordering;
 
 pub use deal::Deal;
 pub use voucher::Voucher;
 
-pub fn deals_and_vouchers(phrases: &[Phrase]) -> (Vec<Deal>, Vec<Voucher>) {
-    let mut deals = deal::find_in(phrases);
-    let mut vouchers = voucher::find_in(phrases);
+/// Estimates of deals and vouchers are the weighted averages of the estimates
+/// of each source, unless there's a learned [`Model`].
+pub fn deals_and_vouchers(
+    phrases: &[Phrase],
+    tuning: &Tuning,
+    model: Option<&Model>,
+) -> (Vec<Deal>, Vec<Voucher>) {
+    let mut deals =
+        deal::find_in(phrases, &tuning.deal, model.map(|m| &m.deal));
+    let mut vouchers =
+        voucher::find_in(phrases, &tuning.voucher, model.map(|m| &m.voucher));
 
     // remove deals which are already exported with vouchers
     deals.retain(|d| {
@@ -68,7 +80,9 @@ pub fn deals_and_vouchers(phrases: &[Phrase]) -> (Vec<Deal>, Vec<Voucher>) {
     let deals = deals
         .into_iter()
         .enumerate()
-        .take_while(|(di, d)| should_retain_offer(*di, d.estimate))
+        .take_while(|(di, d)| {
+            should_retain_offer(*di, d.estimate, &tuning.retain)
+        })
         .map(|(_, d)| d)
         .collect();
 
@@ -76,32 +90,36 @@ pub fn deals_and_vouchers(phrases: &[Phrase]) -> (Vec<Deal>, Vec<Voucher>) {
     let vouchers = vouchers
         .into_iter()
         .enumerate()
-        .take_while(|(vi, v)| should_retain_offer(*vi, v.estimate))
+        .take_while(|(vi, v)| {
+            should_retain_offer(*vi, v.estimate, &tuning.retain)
+        })
         .map(|(_, v)| v)
         .collect();
 
     (deals, vouchers)
 }
 
-fn should_retain_offer(ordinal: usize, estimate: f64) -> bool {
-    const HARD_LIMIT: usize = 12;
-
-    if ordinal > HARD_LIMIT {
+fn should_retain_offer(
+    ordinal: usize,
+    estimate: f64,
+    tuning: &RetainTuning,
+) -> bool {
+    if ordinal > tuning.hard_limit {
         false
     } else {
         let ordinal = ordinal as f64;
 
-        // curve which starts at .8 with first offer and goes slowly to 1.0,
-        // so that by 5th offer it requires .949 estimate, and by 9th .992
-        estimate >= 0.8 + (ordinal + 1.0).ln() / 12.0
+        estimate
+            >= tuning.curve_base + (ordinal + 1.0).ln() / tuning.curve_flatness
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use std::path::PathBuf;
 
     use super::*;
+    use crate::combiner::Logistic;
     use deal::tests::assert_deals_approx_eq;
     use shared::Document;
     use std::fs;
@@ -109,29 +127,32 @@ mod tests {
 
     #[test]
     fn it_should_set_reasonable_thresholds_for_offers() {
-        assert!(should_retain_offer(0, 1.0));
-        assert!(!should_retain_offer(0, 0.5));
-        assert!(should_retain_offer(0, 0.8));
-        assert!(!should_retain_offer(1, 0.8));
-        assert!(should_retain_offer(1, 1.0));
-        assert!(should_retain_offer(1, 0.9));
-
-        assert!(should_retain_offer(2, 1.0));
-        assert!(should_retain_offer(3, 1.0));
-
-        assert!(should_retain_offer(4, 1.0));
-        assert!(!should_retain_offer(4, 0.9));
-
-        assert!(should_retain_offer(8, 1.0));
-        assert!(!should_retain_offer(8, 0.95));
-        assert!(should_retain_offer(5, 0.95));
+        let tuning = RetainTuning::default();
+
+        assert!(should_retain_offer(0, 1.0, &tuning));
+        assert!(!should_retain_offer(0, 0.5, &tuning));
+        assert!(should_retain_offer(0, 0.8, &tuning));
+        assert!(!should_retain_offer(1, 0.8, &tuning));
+        assert!(should_retain_offer(1, 1.0, &tuning));
+        assert!(should_retain_offer(1, 0.9, &tuning));
+
+        assert!(should_retain_offer(2, 1.0, &tuning));
+        assert!(should_retain_offer(3, 1.0, &tuning));
+
+        assert!(should_retain_offer(4, 1.0, &tuning));
+        assert!(!should_retain_offer(4, 0.9, &tuning));
+
+        assert!(should_retain_offer(8, 1.0, &tuning));
+        assert!(!should_retain_offer(8, 0.95, &tuning));
+        assert!(should_retain_offer(5, 0.95, &tuning));
     }
 
     #[test]
     fn it_should_join_adjacent_deals_and_vouchers() {
         let document = testing_document("join_adjacent_deals_and_vouchers");
 
-        let (_, vouchers) = deals_and_vouchers(document.phrases());
+        let (_, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_vouchers_approx_eq(
             vouchers,
@@ -158,7 +179,8 @@ mod tests {
     fn it_should_skip_duplicate_deals_ignore_case() {
         let document = testing_document("deduplicate_deals_ignore_case");
 
-        let (deals, _) = deals_and_vouchers(document.phrases());
+        let (deals, _) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_deals_approx_eq(
             deals,
@@ -196,7 +218,8 @@ mod tests {
     fn it_should_skip_duplicate_vouchers_and_keep_the_longer_phrased_one() {
         let document = testing_document("deduplicate_vouchers");
 
-        let (deals, vouchers) = deals_and_vouchers(document.phrases());
+        let (deals, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_vouchers_approx_eq(
             vouchers,
@@ -210,7 +233,8 @@ mod tests {
     fn it_should_skip_duplicate_deals() {
         let document = testing_document("default");
 
-        let (deals, vouchers) = deals_and_vouchers(document.phrases());
+        let (deals, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_vouchers_approx_eq(
             vouchers,
@@ -232,7 +256,8 @@ mod tests {
     fn bug_skips_offers1() {
         let document = testing_document("bug_skips_offers1");
 
-        let (deals, vouchers) = deals_and_vouchers(document.phrases());
+        let (deals, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_vouchers_approx_eq(
             vouchers,
@@ -263,7 +288,8 @@ mod tests {
     fn bug_skips_offers2() {
         let document = testing_document("bug_skips_offers2");
 
-        let (_, vouchers) = deals_and_vouchers(document.phrases());
+        let (_, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_vouchers_approx_eq(
             vouchers,
@@ -285,7 +311,8 @@ mod tests {
     fn bug_deduplicate_deals1() {
         let document = testing_document("bug_deduplicate_deals1");
 
-        let (deals, _) = deals_and_vouchers(document.phrases());
+        let (deals, _) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
 
         assert_deals_approx_eq(
             deals,
@@ -293,6 +320,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_should_select_with_given_tuning() {
+        let document = testing_document("default");
+        let mut tuning = Tuning::default();
+        tuning.voucher.select_threshold = 1.0;
+        tuning.deal.select_threshold = 1.0;
+
+        let (deals, vouchers) =
+            deals_and_vouchers(document.phrases(), &tuning, None);
+
+        assert_deals_approx_eq(deals, vec![]);
+        assert_vouchers_approx_eq(vouchers, vec![]);
+    }
+
+    #[test]
+    fn it_should_select_with_model() {
+        let document = testing_document("default");
+        let model = Model {
+            deal: Logistic {
+                bias: 10.0,
+                ..Default::default()
+            },
+            voucher: Logistic {
+                bias: -10.0,
+                ..Default::default()
+            },
+        };
+
+        let (deals, vouchers) = deals_and_vouchers(
+            document.phrases(),
+            &Tuning::default(),
+            Some(&model),
+        );
+
+        assert!(!deals.is_empty());
+        assert!(!deals[0].features.is_empty());
+        assert_vouchers_approx_eq(vouchers, vec![]);
+    }
+
+    #[test]
+    fn it_should_describe_selected_offers_without_model() {
+        let document = testing_document("bug_skips_offers1");
+
+        let (deals, vouchers) =
+            deals_and_vouchers(document.phrases(), &Tuning::default(), None);
+
+        assert!(!deals.is_empty());
+        assert!(!vouchers.is_empty());
+        // the features are stored to train the model on
+        assert!(deals.iter().all(|d| !d.features.is_empty()));
+        assert!(vouchers.iter().all(|v| !v.features.is_empty()));
+    }
+
     pub fn testing_document(name: &str) -> Document {
         let curr_path = fs::canonicalize(".").unwrap();
 
@@ -1,14 +1,17 @@
+use crate::{
+    combiner::{self, Features, Logistic},
+    tuning::DealTuning,
+};
 use shared::document::{Phrase, Source};
 use std::{cmp::Ordering, fmt::Display};
 
-/// Skip any phrase which has estimate lower than this.
-const DEAL_SELECT_THRESHOLD: f64 = 0.8;
-
 #[derive(Default, Debug, PartialEq)]
 pub struct Deal {
     pub text: String,
     pub estimate: f64,
     pub link: Option<String>,
+    /// See [`crate::combiner`].
+    pub features: Features,
     // useful for joining adjacent deals and vouchers
     pub(super) first_phrase_index: usize,
     // if multiple adjacent phrases were merged to create this one, this should
@@ -16,37 +19,37 @@ pub struct Deal {
     pub(super) last_phrase_index: Option<usize>,
 }
 
-pub fn find_in(phrases: &[Phrase]) -> Vec<Deal> {
-    // calculates estimates for each phrase based on estimate each method
-    // contributes scaled down by its relevant weight, tweaked for the task
+pub fn find_in(
+    phrases: &[Phrase],
+    tuning: &DealTuning,
+    model: Option<&Logistic>,
+) -> Vec<Deal> {
     let mut estimates = phrases
-        .into_iter()
-        .map(|p| {
+        .iter()
+        .enumerate()
+        .map(|(pi, p)| {
             // special phrase which denotes new paragraph, useful for the step
             // after this one where we join adjacent deals together
             if p.text == "<br>" {
-                return Some(0.0);
+                return Some((0.0, None));
             }
 
-            let top_w = p.top_word()?;
-
-            // dealc is the most relevant
-            let e_d = *p.estimates.get(&Source::Dealc)?; // always there
-            let w_d = 1.0;
-
-            // common phrases are useful only if they match, but they don't tell
-            // us much when they don't match, that's why we've got a limit there
-            let e_c = top_w.estimates.get(&Source::CommonPhrases).copied();
-            let w_c = if e_c.filter(|e_c| *e_c > 0.8).is_some() {
-                0.2
-            } else {
-                0.0
-            };
-            let e_c = e_c.unwrap_or(0.0);
-
-            Some((e_d * w_d + e_c * w_c) / (w_d + w_c))
+            match model {
+                Some(model) => {
+                    let features = combiner::phrase_features(phrases, pi);
+                    Some((model.predict(&features), Some(features)))
+                }
+                None => weighted_estimate(p, tuning).map(|e| (e, None)),
+            }
         })
         .enumerate();
+    // without a model, the features are only calculated for the deals which
+    // are stored, so that they can be labelled and trained on
+    let new_deal = |pi: usize, estimate: f64, features: Option<Features>| {
+        Deal::new(pi, &phrases[pi].text, estimate).with_features(
+            features.unwrap_or_else(|| combiner::phrase_features(phrases, pi)),
+        )
+    };
 
     // let's merge adjacent deals into one
     let mut deals: Vec<Deal> = vec![];
@@ -60,24 +63,29 @@ pub fn find_in(phrases: &[Phrase]) -> Vec<Deal> {
             (Some(_), None) => {
                 cdeal.take().map(|d| deals.push(d));
             }
-            (Some(cde), Some(cpe)) => {
+            (Some(cde), Some((cpe, features))) => {
                 // if they are of similar estimates or both very high, merge them
                 if should_be_merged(cde, cpe) {
-                    cdeal = cdeal.take().map(|d| {
-                        d.merge(&Deal::new(pi, &phrases[pi].text, cpe))
-                    });
+                    // the merged deal only keeps the features of the more
+                    // likely one
+                    let other = if cpe > cde {
+                        new_deal(pi, cpe, features)
+                    } else {
+                        Deal::new(pi, &phrases[pi].text, cpe)
+                    };
+                    cdeal = cdeal.take().map(|d| d.merge(&other));
                 } else {
                     // they differ in estimate too much, separate them out
                     cdeal.take().map(|d| deals.push(d));
-                    if cpe > DEAL_SELECT_THRESHOLD {
-                        cdeal = Some(Deal::new(pi, &phrases[pi].text, cpe));
+                    if cpe > tuning.select_threshold {
+                        cdeal = Some(new_deal(pi, cpe, features));
                     }
                 }
             }
-            (None, Some(cpe)) => {
+            (None, Some((cpe, features))) => {
                 // there was no deal to append it to, start a new one
-                if cpe > DEAL_SELECT_THRESHOLD {
-                    cdeal = Some(Deal::new(pi, &phrases[pi].text, cpe));
+                if cpe > tuning.select_threshold {
+                    cdeal = Some(new_deal(pi, cpe, features));
                 }
             }
         }
@@ -86,6 +94,29 @@ pub fn find_in(phrases: &[Phrase]) -> Vec<Deal> {
     deals
 }
 
+/// Calculates estimate of the phrase based on estimate each method contributes
+/// scaled down by its relevant weight, tweaked for the task.
+fn weighted_estimate(p: &Phrase, tuning: &DealTuning) -> Option<f64> {
+    let top_w = p.top_word()?;
+
+    // dealc is the most relevant
+    let e_d = *p.estimates.get(&Source::Dealc)?; // always there
+    let w_d = tuning.dealc_weight;
+
+    // common phrases are useful only if they match, but they don't tell us much
+    // when they don't match, that's why we've got a limit there
+    let e_c = top_w.estimates.get(&Source::CommonPhrases).copied();
+    let w_c = if e_c.filter(|e_c| *e_c > tuning.common_phrases_min).is_some() {
+        tuning.common_phrases_weight
+    } else {
+        0.0
+    };
+    let e_c = e_c.unwrap_or(0.0);
+
+    let e = (e_d * w_d + e_c * w_c) / (w_d + w_c);
+    Some(e * p.confidence_weight())
+}
+
 impl Deal {
     pub fn new(phrase_index: usize, text: impl Display, estimate: f64) -> Self {
         let text = text.to_string();
@@ -97,9 +128,18 @@ impl Deal {
         }
     }
 
+    fn with_features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// The merged deal is described by the features of the more likely one.
     fn merge(mut self, other: &Self) -> Self {
         self.text.push(' ');
         self.text.push_str(&other.text);
+        if other.estimate > self.estimate {
+            self.features = other.features.clone();
+        }
         self.estimate = self.estimate.max(other.estimate);
         self
     }
@@ -156,7 +196,7 @@ pub mod tests {
         let document = testing_document("join_adjacent_deals");
 
         assert_deals_approx_eq(
-            find_in(document.phrases()),
+            find_in(document.phrases(), &DealTuning::default(), None),
             vec![
                 Deal::new(0, "50% off everything", 0.94),
                 Deal::new(0, "30 Apr 2021 Excellent service", 0.85),
@@ -190,7 +230,7 @@ pub mod tests {
         let document = testing_document("default");
 
         assert_deals_approx_eq(
-            find_in(document.phrases()),
+            find_in(document.phrases(), &DealTuning::default(), None),
             vec![
                 Deal::new(
                     0,
@@ -1,52 +1,55 @@
-use shared::document::{Phrase, Source};
+use crate::{
+    combiner::{self, Features, Logistic},
+    tuning::VoucherTuning,
+};
+use shared::document::{Phrase, Source, Word};
 use std::{cmp::Ordering, fmt::Display};
 
-/// Skip any voucher which has estimate lower than this.
-const VOUCHER_SELECT_THRESHOLD: f64 = 0.8;
-
 #[derive(Default, Debug, PartialEq)]
 pub struct Voucher {
     pub phrase: String,
     pub text: String,
     pub estimate: f64,
     pub link: Option<String>,
+    /// See [`crate::combiner`].
+    pub features: Features,
     // useful for joining adjacent deals and vouchers
     pub(super) phrase_index: usize,
 }
 
-pub fn find_in(phrases: &[Phrase]) -> Vec<Voucher> {
+pub fn find_in(
+    phrases: &[Phrase],
+    tuning: &VoucherTuning,
+    model: Option<&Logistic>,
+) -> Vec<Voucher> {
     phrases
-        .into_iter()
+        .iter()
         .enumerate()
         .filter_map(|(pi, p)| {
-            let e_d = *p.estimates.get(&Source::Dealc)?; // always there
-            let w_d = 0.5;
-
             let vouchers: Vec<_> = p
                 .words
                 .iter()
-                .filter_map(|w| {
-                    let e_v = *w.estimates.get(&Source::Voucherc)?; // always there
-                    let w_v = 0.8;
-
-                    let e_o = w.estimates.get(&Source::OpenAi).copied();
-                    let w_o = if e_o.is_some() { 0.6 } else { 0.0 };
-                    let e_o = e_o.unwrap_or(0.0);
-
-                    let e_c = w.estimates.get(&Source::CommonPhrases).copied();
-                    let w_c = if e_c.is_some() { 1.0 } else { 0.0 };
-                    let e_c = e_c.unwrap_or(0.0);
-
-                    let e = (e_d * w_d + e_v * w_v + e_c * w_c + e_o * w_o)
-                        / (w_d + w_v + w_c + w_o);
-
-                    if e > VOUCHER_SELECT_THRESHOLD {
-                        Some(Voucher::new(
-                            pi,
-                            p.text.clone(),
-                            w.text.clone(),
-                            e,
-                        ))
+                .enumerate()
+                .filter_map(|(wi, w)| {
+                    let (e, features) = match model {
+                        Some(model) => {
+                            let features =
+                                combiner::word_features(phrases, pi, wi);
+                            (model.predict(&features), Some(features))
+                        }
+                        None => (weighted_estimate(p, w, tuning)?, None),
+                    };
+
+                    if e > tuning.select_threshold {
+                        // selected vouchers are stored with their features so
+                        // that they can be labelled and trained on
+                        let features = features.unwrap_or_else(|| {
+                            combiner::word_features(phrases, pi, wi)
+                        });
+                        Some(
+                            Voucher::new(pi, p.text.clone(), w.text.clone(), e)
+                                .with_features(features),
+                        )
                     } else {
                         None
                     }
@@ -63,6 +66,42 @@ pub fn find_in(phrases: &[Phrase]) -> Vec<Voucher> {
         .collect()
 }
 
+/// Weighted average of the estimates of the word and of the dealc estimate of
+/// its phrase.
+fn weighted_estimate(
+    p: &Phrase,
+    w: &Word,
+    tuning: &VoucherTuning,
+) -> Option<f64> {
+    let e_d = *p.estimates.get(&Source::Dealc)?; // always there
+    let w_d = tuning.dealc_weight;
+
+    let e_v = *w.estimates.get(&Source::Voucherc)?; // always there
+    let w_v = tuning.voucherc_weight;
+
+    let e_o = w.estimates.get(&Source::OpenAi).copied();
+    let w_o = if e_o.is_some() {
+        tuning.openai_weight
+    } else {
+        0.0
+    };
+    let e_o = e_o.unwrap_or(0.0);
+
+    let e_c = w.estimates.get(&Source::CommonPhrases).copied();
+    let w_c = if e_c.is_some() {
+        tuning.common_phrases_weight
+    } else {
+        0.0
+    };
+    let e_c = e_c.unwrap_or(0.0);
+
+    Some(
+        (e_d * w_d + e_v * w_v + e_c * w_c + e_o * w_o)
+            / (w_d + w_v + w_c + w_o)
+            * w.confidence_weight(),
+    )
+}
+
 impl Voucher {
     pub fn new(
         phrase_index: usize,
@@ -81,6 +120,11 @@ impl Voucher {
         }
     }
 
+    fn with_features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
     pub(super) fn cmp_estimates(&self, other: &Self) -> Ordering {
         self.estimate
             .partial_cmp(&other.estimate)
@@ -98,7 +142,7 @@ pub mod tests {
         let document = testing_document("default");
 
         assert_vouchers_approx_eq(
-            find_in(document.phrases()),
+            find_in(document.phrases(), &VoucherTuning::default(), None),
             vec![
                 Voucher::new(
                     0,
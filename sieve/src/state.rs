@@ -1,10 +1,11 @@
-use crate::prelude::*;
-use shared::{S3Ext, SqsExt};
+use crate::{combiner::Model, prelude::*, tuning::Tuning};
+use shared::S3Ext;
 use sqlite::Connection;
 
 pub struct State {
     pub conf: Conf,
-    pub sqs: Box<dyn SqsExt>,
     pub s3: Box<dyn S3Ext>,
     pub db: Connection,
+    pub tuning: Tuning,
+    pub model: Option<Model>,
 }
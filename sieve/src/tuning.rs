@@ -0,0 +1,149 @@
+//! Weights and thresholds with which we select offers from the estimates. They
+//! are loaded from a JSON file, see [`Conf::tuning_path`], so that each
+//! deployment, e.g. per customer or language, can be tuned without recompiling.
+//!
+//! Each file has a version, which is stored with each offer selected with it.
+//! Omitted values fall back to the defaults.
+
+use crate::prelude::*;
+use serde::Deserialize;
+use std::fs;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Tuning {
+    /// Change it with every change to the file.
+    pub version: String,
+    #[serde(default)]
+    pub deal: DealTuning,
+    #[serde(default)]
+    pub voucher: VoucherTuning,
+    #[serde(default)]
+    pub retain: RetainTuning,
+}
+
+/// The estimate of a phrase is the weighted average of the estimates of each
+/// source.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DealTuning {
+    pub dealc_weight: f64,
+    pub common_phrases_weight: f64,
+    /// Common phrases are useful only if they match, but they don't tell us
+    /// much when they don't match. Their estimate counts only if it's higher
+    /// than this.
+    pub common_phrases_min: f64,
+    /// Skip any phrase which has estimate lower than this.
+    pub select_threshold: f64,
+}
+
+/// The estimate of a word is the weighted average of the estimates of each
+/// source. Sources which didn't estimate the word don't count.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct VoucherTuning {
+    /// Applies to the estimate of the phrase the word is in.
+    pub dealc_weight: f64,
+    pub voucherc_weight: f64,
+    pub openai_weight: f64,
+    pub common_phrases_weight: f64,
+    /// Skip any voucher which has estimate lower than this.
+    pub select_threshold: f64,
+}
+
+/// How many of the deals and vouchers, each sorted by estimate, we keep. The
+/// n-th offer is kept if its estimate is at least
+/// `curve_base + ln(n) / curve_flatness`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetainTuning {
+    /// Offers after this many are never kept.
+    pub hard_limit: usize,
+    pub curve_base: f64,
+    pub curve_flatness: f64,
+}
+
+impl Tuning {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            Error::fatal(format!("Cannot read tuning file {}: {}", path, e))
+                .with_code("tuning")
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| {
+            Error::fatal(format!("Invalid tuning file {}: {}", path, e))
+                .with_code("tuning")
+        })
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            version: "default".to_string(),
+            deal: DealTuning::default(),
+            voucher: VoucherTuning::default(),
+            retain: RetainTuning::default(),
+        }
+    }
+}
+
+impl Default for DealTuning {
+    fn default() -> Self {
+        Self {
+            dealc_weight: 1.0,
+            common_phrases_weight: 0.2,
+            common_phrases_min: 0.8,
+            select_threshold: 0.8,
+        }
+    }
+}
+
+impl Default for VoucherTuning {
+    fn default() -> Self {
+        Self {
+            dealc_weight: 0.5,
+            voucherc_weight: 0.8,
+            openai_weight: 0.6,
+            common_phrases_weight: 1.0,
+            select_threshold: 0.8,
+        }
+    }
+}
+
+impl Default for RetainTuning {
+    fn default() -> Self {
+        Self {
+            hard_limit: 12,
+            // starts at .8 with first offer and goes slowly to 1.0, so that by
+            // 5th offer it requires .949 estimate, and by 9th .992
+            curve_base: 0.8,
+            curve_flatness: 12.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_falls_back_to_defaults() {
+        let tuning: Tuning = serde_json::from_str(
+            r#"{
+                "version": "de-2",
+                "voucher": { "openai_weight": 0.0 },
+                "retain": { "hard_limit": 5 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(tuning.version, "de-2");
+        assert_eq!(tuning.deal, DealTuning::default());
+        assert_eq!(tuning.voucher.openai_weight, 0.0);
+        assert_eq!(tuning.voucher.voucherc_weight, 0.8);
+        assert_eq!(tuning.retain.hard_limit, 5);
+        assert_eq!(tuning.retain.curve_base, 0.8);
+
+        assert!(serde_json::from_str::<Tuning>("{}").is_err());
+    }
+}